@@ -1,5 +1,6 @@
 const { execSync } = require("node:child_process");
 const fs = require("fs");
+const os = require("os");
 const path = require("path");
 const https = require("https");
 const version = require("./version").version;
@@ -243,14 +244,34 @@ function makeExecutable(binaryPath) {
   }
 }
 
+/**
+ * Puts the binary on the PATH of later steps, for exec and doctor mode
+ */
+function addToPath(binaryPath) {
+  if ("GITHUB_PATH" in process.env) {
+    fs.appendFileSync(
+      process.env.GITHUB_PATH,
+      `${path.dirname(binaryPath)}${os.EOL}`
+    );
+  }
+}
+
 async function run() {
   try {
     const binaryPath = await getBinary();
     makeExecutable(binaryPath);
+    addToPath(binaryPath);
+
+    if (process.env.INPUT_INSTALL_ONLY === "true") {
+      console.log(`Installed sm-action at: ${binaryPath}`);
+      return;
+    }
+
     execSync(binaryPath, { stdio: "inherit" });
   } catch (error) {
     console.error(`Error: ${error.message}`);
-    process.exit(1);
+    // pass on the binary's exit code, which tells the kind of failure apart
+    process.exit(error.status ?? 1);
   }
 }
 
@@ -0,0 +1,359 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Utc};
+use url::{Host, Url};
+use uuid::Uuid;
+
+use crate::config::{Account, Config, DEFAULT_ACCOUNT};
+use crate::{proxy, tls};
+
+/// Clocks further apart than this make tokens look expired or not yet valid.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+/// One line of the checklist.
+#[derive(Debug)]
+struct Check {
+    status: Status,
+    name: String,
+    detail: String,
+}
+
+impl Check {
+    fn new(status: Status, name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            name: name.into(),
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Checks the inputs, and that the servers can be reached, for `sm-action doctor`. Prints a
+/// checklist and returns 1 if any check failed. Access tokens are never printed.
+pub fn run() -> Result<i32> {
+    // the proxy and CA certificates are configured the same way as when the action runs, through
+    // the environment, before the runtime starts any threads
+    let inputs = Config::new().and_then(|config| {
+        let accounts = config.all_accounts()?;
+        proxy::configure(&config)?;
+        tls::configure(&config)?;
+        Ok((config, accounts))
+    });
+
+    let checks = crate::runtime()?.block_on(checks(inputs));
+    print!("{}", render(&checks));
+
+    let failed = checks.iter().any(|check| check.status == Status::Fail);
+    Ok(if failed { 1 } else { 0 })
+}
+
+async fn checks(inputs: Result<(Config, Vec<Account>)>) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let (config, accounts) = match inputs {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            checks.push(Check::new(Status::Fail, "Inputs", format!("{e:#}")));
+            return checks;
+        }
+    };
+    checks.push(Check::new(Status::Pass, "Inputs", "valid"));
+
+    let mut urls: Vec<&str> = Vec::new();
+    for account in &accounts {
+        let name = match account.name.as_str() {
+            DEFAULT_ACCOUNT => "Access token".to_string(),
+            name => format!("Access token for account '{name}'"),
+        };
+
+        if account.name == DEFAULT_ACCOUNT && config.oidc_exchange_url.is_some() {
+            checks.push(Check::new(
+                Status::Skip,
+                name,
+                "exchanged for the GitHub OIDC token when the action runs",
+            ));
+        } else {
+            checks.push(match validate_access_token(&account.access_token) {
+                Ok(()) => Check::new(Status::Pass, name, "well-formed"),
+                Err(e) => Check::new(Status::Fail, name, e.to_string()),
+            });
+        }
+
+        for url in [account.identity_url.as_str(), account.api_url.as_str()] {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+
+    let http = match tls::http_client(&config) {
+        Ok(http) => http,
+        Err(e) => {
+            checks.push(Check::new(Status::Fail, "TLS settings", format!("{e:#}")));
+            return checks;
+        }
+    };
+    checks.push(check_tls(config.ca_bundle.as_deref()));
+
+    let behind_proxy = proxy::configured();
+    let mut looked_up: Vec<String> = Vec::new();
+    let mut server_time: Option<(&str, DateTime<Utc>, DateTime<Utc>)> = None;
+
+    for url in urls {
+        // the API and identity servers usually share a host
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from));
+        if host.as_ref().is_none_or(|host| !looked_up.contains(host)) {
+            checks.push(check_dns(url, behind_proxy).await);
+            looked_up.extend(host);
+        }
+
+        let (check, date) = check_connection(&http, url).await;
+        checks.push(check);
+        if server_time.is_none()
+            && let Some(date) = date
+        {
+            server_time = Some((url, date, Utc::now()));
+        }
+    }
+
+    checks.push(check_clock(server_time));
+    checks
+}
+
+async fn check_dns(url: &str, behind_proxy: bool) -> Check {
+    let Ok(parsed) = Url::parse(url) else {
+        return Check::new(Status::Fail, format!("DNS lookup for {url}"), "invalid URL");
+    };
+
+    let host = match parsed.host() {
+        Some(Host::Domain(host)) => host,
+        _ => {
+            return Check::new(
+                Status::Skip,
+                format!("DNS lookup for {url}"),
+                "the host is an IP address",
+            );
+        }
+    };
+    let name = format!("DNS lookup of {host}");
+
+    if behind_proxy {
+        return Check::new(Status::Skip, name, "requests go through a proxy");
+    }
+
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(addresses) => match addresses.count() {
+            0 => Check::new(Status::Fail, name, "no addresses found"),
+            1 => Check::new(Status::Pass, name, "1 address"),
+            count => Check::new(Status::Pass, name, format!("{count} addresses")),
+        },
+        Err(e) => Check::new(Status::Fail, name, e.to_string()),
+    }
+}
+
+/// Reports which CA certificates servers are verified against. The connection checks use the
+/// same TLS setup as the SDK, so they trust the same certificates as a real run.
+fn check_tls(ca_bundle: Option<&str>) -> Check {
+    let name = "TLS settings";
+    match ca_bundle {
+        None => Check::new(Status::Pass, name, "trusting the system CA certificates"),
+        Some(path) if tls::SSL_CERT_FILE_SUPPORTED => Check::new(
+            Status::Pass,
+            name,
+            format!("trusting the system CA certificates and those in {path}"),
+        ),
+        Some(_) => Check::new(
+            Status::Skip,
+            name,
+            format!(
+                "ca_bundle is ignored on {}; trusting the system CA certificates only",
+                std::env::consts::OS
+            ),
+        ),
+    }
+}
+
+/// Sends a request to the server, verifying it the same way as the SDK. Any response means it
+/// could be reached, over a verified TLS connection for `https://` URLs. Returns the server's
+/// `Date`, if it sent one.
+async fn check_connection(http: &reqwest::Client, url: &str) -> (Check, Option<DateTime<Utc>>) {
+    let encrypted = url.starts_with("https://");
+    let name = if encrypted {
+        format!("TLS connection to {url}")
+    } else {
+        format!("Connection to {url}")
+    };
+
+    let response = match http.get(url).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            let error = anyhow::Error::from(e);
+            return (Check::new(Status::Fail, name, format!("{error:#}")), None);
+        }
+    };
+
+    let date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        .map(|date| date.with_timezone(&Utc));
+
+    let mut detail = format!("responded with {}", response.status());
+    if !encrypted {
+        detail.push_str(", but the connection is not encrypted");
+    }
+
+    (Check::new(Status::Pass, name, detail), date)
+}
+
+/// Compares the clock of the runner with a server's `Date`, observed at `local`.
+fn check_clock(server_time: Option<(&str, DateTime<Utc>, DateTime<Utc>)>) -> Check {
+    let name = "Clock";
+    let Some((url, server, local)) = server_time else {
+        return Check::new(Status::Skip, name, "no server responded with its time");
+    };
+
+    let skew = (local - server).abs().to_std().unwrap_or_default();
+    let direction = if local > server { "ahead of" } else { "behind" };
+    let detail = format!("{}s {direction} {url}", skew.as_secs());
+
+    if skew > MAX_CLOCK_SKEW {
+        Check::new(Status::Fail, name, detail)
+    } else {
+        Check::new(Status::Pass, name, detail)
+    }
+}
+
+/// Checks that the access token looks like `0.<client id>.<client secret>:<encryption key>`.
+/// The errors never include any part of the token.
+fn validate_access_token(token: &str) -> Result<()> {
+    let Some((credentials, key)) = token.trim().split_once(':') else {
+        bail!("must have the form 0.<client id>.<client secret>:<encryption key>");
+    };
+
+    let parts: Vec<&str> = credentials.split('.').collect();
+    let [version, client_id, client_secret] = parts[..] else {
+        bail!("must have the form 0.<client id>.<client secret>:<encryption key>");
+    };
+
+    if version != "0" {
+        bail!("has an unsupported version; expected 0");
+    }
+    if Uuid::parse_str(client_id).is_err() {
+        bail!("does not contain a valid client Id");
+    }
+    if client_secret.is_empty() {
+        bail!("does not contain a client secret");
+    }
+    if !STANDARD.decode(key).is_ok_and(|key| key.len() == 16) {
+        bail!("does not contain a valid encryption key");
+    }
+
+    Ok(())
+}
+
+fn render(checks: &[Check]) -> String {
+    let mut output = String::from("Bitwarden doctor\n");
+
+    for check in checks {
+        let status = match check.status {
+            Status::Pass => "PASS",
+            Status::Fail => "FAIL",
+            Status::Skip => "SKIP",
+        };
+        output.push_str(&format!("  [{status}] {}: {}\n", check.name, check.detail));
+    }
+
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    output.push_str(&format!(
+        "{} passed, {} failed, {} skipped\n",
+        count(Status::Pass),
+        count(Status::Fail),
+        count(Status::Skip)
+    ));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_access_token() {
+        let key = STANDARD.encode([7u8; 16]);
+        let client_id = Uuid::new_v4();
+
+        assert!(validate_access_token(&format!("0.{client_id}.secret:{key}")).is_ok());
+
+        for token in [
+            String::new(),
+            format!("0.{client_id}.secret"),
+            format!("1.{client_id}.secret:{key}"),
+            format!("0.not-a-uuid.secret:{key}"),
+            format!("0.{client_id}.:{key}"),
+            format!("0.{client_id}.secret:{}", STANDARD.encode([7u8; 8])),
+        ] {
+            let error = validate_access_token(&token).unwrap_err();
+            assert!(!error.to_string().contains("secret:"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_check_clock() {
+        let server = DateTime::parse_from_rfc2822("Sun, 06 Nov 1994 08:49:37 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        let url = "https://identity.example.com";
+
+        let check = check_clock(Some((url, server, server + chrono::Duration::seconds(30))));
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.detail, "30s ahead of https://identity.example.com");
+
+        let check = check_clock(Some((url, server, server - chrono::Duration::minutes(10))));
+        assert_eq!(check.status, Status::Fail);
+        assert_eq!(check.detail, "600s behind https://identity.example.com");
+
+        assert_eq!(check_clock(None).status, Status::Skip);
+    }
+
+    #[test]
+    fn test_check_tls() {
+        let check = check_tls(None);
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.detail, "trusting the system CA certificates");
+
+        let check = check_tls(Some("ca.pem"));
+        if tls::SSL_CERT_FILE_SUPPORTED {
+            assert_eq!(check.status, Status::Pass);
+            assert!(check.detail.ends_with("and those in ca.pem"));
+        } else {
+            assert_eq!(check.status, Status::Skip);
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let output = render(&[
+            Check::new(Status::Pass, "Inputs", "valid"),
+            Check::new(Status::Fail, "DNS lookup of vault.example.com", "not found"),
+        ]);
+
+        assert!(output.contains("  [PASS] Inputs: valid\n"));
+        assert!(output.contains("  [FAIL] DNS lookup of vault.example.com: not found\n"));
+        assert!(output.ends_with("1 passed, 1 failed, 0 skipped\n"));
+    }
+}
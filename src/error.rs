@@ -0,0 +1,264 @@
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// Every warning printed during the run, for the run report.
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Failures that get their own GitHub annotation and exit code, so that users and workflows can
+/// tell them apart. Any other error exits with code 1.
+#[derive(Debug)]
+pub enum ActionError {
+    /// The access token was rejected by the identity server.
+    Auth(String),
+    /// The server could not be reached at all.
+    Unreachable { url: String, message: String },
+    /// These secrets do not exist, or the machine account cannot see them.
+    SecretsNotFound(Vec<(Uuid, String)>),
+    /// The machine account is not allowed to read the requested secrets.
+    PermissionDenied(String),
+    /// The secrets were returned but could not be decrypted.
+    Decryption(String),
+    /// A request took longer than `request_timeout`, or the run longer than `timeout`.
+    Timeout {
+        phase: String,
+        url: String,
+        limit: Duration,
+        overall: bool,
+    },
+}
+
+impl std::fmt::Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auth(message) => write!(
+                f,
+                "Authentication with Bitwarden failed. Check that the access token is valid and has not expired.\nError: {message}"
+            ),
+            Self::Unreachable { url, message } => write!(
+                f,
+                "Could not connect to {url}. Check the server URLs and the runner's network access.\nError: {message}"
+            ),
+            Self::SecretsNotFound(secrets) => {
+                writeln!(
+                    f,
+                    "The following secrets could not be found. Check that they exist and that the machine account has access to them:"
+                )?;
+                let lines: Vec<String> = secrets
+                    .iter()
+                    .map(|(id, name)| format!("  {id} > {name}"))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::PermissionDenied(message) => write!(
+                f,
+                "The machine account is not permitted to read the requested secrets.\nError: {message}"
+            ),
+            Self::Decryption(message) => write!(
+                f,
+                "The secrets could not be decrypted. Check that the access token belongs to the organization that owns the secrets.\nError: {message}"
+            ),
+            Self::Timeout {
+                phase,
+                url,
+                limit,
+                overall: false,
+            } => write!(
+                f,
+                "{phase} timed out after {limit:?} waiting for {url}. Increase request_timeout if the server is slow."
+            ),
+            Self::Timeout {
+                phase,
+                url,
+                limit,
+                overall: true,
+            } => write!(
+                f,
+                "{phase} did not finish within the overall timeout of {limit:?}, waiting for {url}. Increase timeout if the server is slow."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+impl ActionError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Auth(_) => 10,
+            Self::Unreachable { .. } => 11,
+            Self::SecretsNotFound(_) => 12,
+            Self::PermissionDenied(_) => 13,
+            Self::Decryption(_) => 14,
+            Self::Timeout { .. } => 15,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Auth(_) => "Authentication failed",
+            Self::Unreachable { .. } => "Server unreachable",
+            Self::SecretsNotFound(_) => "Secrets not found",
+            Self::PermissionDenied(_) => "Permission denied",
+            Self::Decryption(_) => "Decryption failed",
+            Self::Timeout { .. } => "Timed out",
+        }
+    }
+
+    /// Classifies an error from logging in with an access token.
+    pub fn from_login(error: &anyhow::Error, identity_url: &str) -> Self {
+        let message = error.to_string();
+        match Kind::of(&message) {
+            Kind::Unreachable => Self::Unreachable {
+                url: identity_url.to_string(),
+                message,
+            },
+            _ => Self::Auth(message),
+        }
+    }
+
+    /// Classifies an error from fetching secrets. Missing secrets are reported with `None`, since
+    /// the SDK does not say which of the requested secrets were missing.
+    pub fn from_fetch(error: &anyhow::Error, api_url: &str) -> Option<Self> {
+        let message = error.to_string();
+        match Kind::of(&message) {
+            Kind::Unreachable => Some(Self::Unreachable {
+                url: api_url.to_string(),
+                message,
+            }),
+            Kind::Unauthorized => Some(Self::Auth(message)),
+            Kind::Forbidden => Some(Self::PermissionDenied(message)),
+            Kind::Decryption => Some(Self::Decryption(message)),
+            Kind::NotFound | Kind::Other => None,
+        }
+    }
+}
+
+/// Returns whether an error from fetching a single secret means that it is missing.
+pub fn is_not_found(error: &dyn std::fmt::Display) -> bool {
+    matches!(Kind::of(&error.to_string()), Kind::NotFound)
+}
+
+/// The Bitwarden SDK only exposes the kind of failure through its error message.
+enum Kind {
+    Unreachable,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Decryption,
+    Other,
+}
+
+impl Kind {
+    fn of(message: &str) -> Self {
+        let message = message.to_lowercase();
+        let contains_any = |needles: &[&str]| needles.iter().any(|n| message.contains(n));
+
+        if contains_any(&["error sending request", "dns error", "connection refused"]) {
+            Self::Unreachable
+        } else if message.contains("401 unauthorized") {
+            Self::Unauthorized
+        } else if message.contains("403 forbidden") {
+            Self::Forbidden
+        } else if message.contains("not found") {
+            Self::NotFound
+        } else if contains_any(&["crypto", "decrypt"]) {
+            Self::Decryption
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Prints the error as a GitHub error annotation and returns the exit code to exit with.
+pub fn report(error: &anyhow::Error) -> i32 {
+    match error.downcast_ref::<ActionError>() {
+        Some(e) => {
+            println!(
+                "::error title={}::{}",
+                escape_property(e.title()),
+                escape_data(&e.to_string())
+            );
+            e.exit_code()
+        }
+        None => {
+            println!("::error::{}", escape_data(&format!("{error:#}")));
+            1
+        }
+    }
+}
+
+/// Prints a GitHub warning annotation.
+pub fn warning(message: &str) {
+    println!("::warning::{}", escape_data(message));
+    WARNINGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(message.to_string());
+}
+
+/// Returns the warnings printed so far.
+pub fn warnings() -> Vec<String> {
+    WARNINGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Escapes a workflow command message, which ends at the first newline.
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a workflow command property, which additionally ends at `:` or `,`.
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_fetch() {
+        let api_url = "https://api.example.com";
+
+        let error = anyhow::anyhow!("error sending request for url (https://api.example.com/)");
+        assert_eq!(
+            ActionError::from_fetch(&error, api_url)
+                .unwrap()
+                .exit_code(),
+            11
+        );
+
+        let error = anyhow::anyhow!("Received error message from server: [403 Forbidden] ");
+        assert_eq!(
+            ActionError::from_fetch(&error, api_url)
+                .unwrap()
+                .exit_code(),
+            13
+        );
+
+        let error = anyhow::anyhow!("Received error message from server: [404 Not Found] ");
+        assert!(ActionError::from_fetch(&error, api_url).is_none());
+        assert!(is_not_found(&error));
+    }
+
+    #[test]
+    fn test_secrets_not_found_names_each_secret() {
+        let id = Uuid::new_v4();
+        let error = ActionError::SecretsNotFound(vec![(id, "DB_PASSWORD".to_string())]);
+
+        assert!(error.to_string().contains(&format!("{id} > DB_PASSWORD")));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape_data("50%\nline two\r"), "50%25%0Aline two%0D");
+        assert_eq!(escape_property("a: b, c"), "a%3A b%2C c");
+    }
+}
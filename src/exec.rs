@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::process::ExitStatus;
+
+use anyhow::{Result, bail};
+use tokio::process::{Child, Command};
+
+use crate::debug;
+
+/// Inputs holding access tokens, which the command must not inherit: with them it could fetch any
+/// secret the machine accounts have access to.
+const CREDENTIAL_VARS: &[&str] = &["INPUT_ACCESS_TOKEN", "INPUT_ACCOUNTS"];
+
+/// Returns the child command for `sm-action exec -- <cmd> [args...]`, or `None` if the action
+/// was not started in exec mode.
+pub fn command_from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Vec<String>>> {
+    let mut args = args.into_iter().skip(1).peekable();
+
+    if args.peek().map(String::as_str) != Some("exec") {
+        return Ok(None);
+    }
+    args.next();
+
+    if args.peek().map(String::as_str) == Some("--") {
+        args.next();
+    }
+
+    let command: Vec<String> = args.collect();
+    if command.is_empty() {
+        bail!("exec requires a command to run, e.g. 'sm-action exec -- ./deploy.sh'");
+    }
+
+    Ok(Some(command))
+}
+
+/// Runs `command` with `env` added to its environment and waits for it to finish. Signals
+/// received while waiting are forwarded to the child. Returns the exit code to exit with.
+pub async fn run(command: &[String], env: HashMap<String, String>) -> Result<i32> {
+    let program = command.first().map(String::as_str).unwrap_or_default();
+    debug!(
+        "Running '{program}' with {} secrets in its environment",
+        env.len()
+    );
+
+    let child = build(command, env)?
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to start '{program}'.\nError: {e}"))?;
+
+    wait(child).await
+}
+
+/// Builds the child process, with `env` added to the action's environment and the access tokens
+/// removed from it.
+fn build(command: &[String], env: HashMap<String, String>) -> Result<Command> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("exec requires a command to run"))?;
+
+    let mut child = Command::new(program);
+    child.args(args);
+    for var in CREDENTIAL_VARS {
+        child.env_remove(var);
+    }
+    child.envs(env);
+
+    Ok(child)
+}
+
+/// Waits for the child to exit, forwarding SIGINT, SIGTERM and SIGHUP to it in the meantime.
+#[cfg(unix)]
+async fn wait(mut child: Child) -> Result<i32> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    loop {
+        let forwarded = tokio::select! {
+            status = child.wait() => return Ok(exit_code(status?)),
+            _ = sigint.recv() => libc::SIGINT,
+            _ = sigterm.recv() => libc::SIGTERM,
+            _ = sighup.recv() => libc::SIGHUP,
+        };
+
+        if let Some(pid) = child.id() {
+            debug!("Forwarding signal {forwarded} to child process {pid}");
+            // SAFETY: `pid` belongs to a child we spawned and have not reaped yet
+            unsafe { libc::kill(pid as libc::pid_t, forwarded) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait(mut child: Child) -> Result<i32> {
+    Ok(exit_code(child.wait().await?))
+}
+
+/// Mirrors the child's exit status. A child killed by a signal exits with `128 + signal`, as a
+/// shell would report it.
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_command_from_args() {
+        assert_eq!(command_from_args(args(&["sm-action"])).unwrap(), None);
+        assert_eq!(
+            command_from_args(args(&["sm-action", "exec", "--", "env", "-0"])).unwrap(),
+            Some(args(&["env", "-0"]))
+        );
+        assert_eq!(
+            command_from_args(args(&["sm-action", "exec", "env"])).unwrap(),
+            Some(args(&["env"]))
+        );
+        assert_eq!(
+            command_from_args(args(&["sm-action", "exec", "--", "./ci.sh", "--test"])).unwrap(),
+            Some(args(&["./ci.sh", "--test"]))
+        );
+        assert!(command_from_args(args(&["sm-action", "exec", "--"])).is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_passes_env_and_exit_code() {
+        let env = HashMap::from([("SM_ACTION_EXEC_TEST".to_string(), "42".to_string())]);
+        let code = run(&args(&["sh", "-c", "exit \"$SM_ACTION_EXEC_TEST\""]), env)
+            .await
+            .unwrap();
+
+        assert_eq!(code, 42);
+
+        let command = build(&args(&["env"]), HashMap::new()).unwrap();
+        let envs: Vec<_> = command.as_std().get_envs().collect();
+        for var in CREDENTIAL_VARS {
+            assert!(
+                envs.contains(&(OsStr::new(var), None)),
+                "{var} is inherited"
+            );
+        }
+    }
+}
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use bitwarden_core::Client;
+use bitwarden_sm::projects::ProjectsListRequest;
+use bitwarden_sm::secrets::{SecretIdentifiersByProjectRequest, SecretIdentifiersRequest};
+use bitwarden_sm::{ClientProjectsExt, ClientSecretsExt};
+use uuid::Uuid;
+
+use crate::mapping::SecretMapping;
+use crate::timeout::Timeouts;
+use crate::{debug, error};
+
+/// The phase reported when a list request times out.
+const PHASE: &str = "Resolving secret references";
+
+/// A reference to a secret, as written on the left-hand side of a `secrets` input line.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SecretRef {
+    /// `UUID > NAME`
+    Id(Uuid),
+    /// `key > NAME` or `project/key > NAME`, where `project` is a project name or UUID
+    Key {
+        project: Option<String>,
+        key: String,
+    },
+    /// `project:UUID > PREFIX_*`; every secret in the project
+    Project(Uuid),
+}
+
+impl FromStr for SecretRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            bail!("Secret reference must not be empty");
+        }
+
+        if let Ok(id) = Uuid::from_str(s) {
+            return Ok(Self::Id(id));
+        }
+
+        if let Some(project) = s.strip_prefix("project:") {
+            let project = project.trim();
+            let project_id = Uuid::from_str(project)
+                .map_err(|_| anyhow::anyhow!("Invalid project UUID format: {project}"))?;
+            return Ok(Self::Project(project_id));
+        }
+
+        match s.split_once('/') {
+            Some((project, key)) => {
+                let (project, key) = (project.trim(), key.trim());
+                if project.is_empty() || key.is_empty() {
+                    bail!("Invalid secret reference: {s}. Expected 'project/key'");
+                }
+                Ok(Self::Key {
+                    project: Some(project.to_string()),
+                    key: key.to_string(),
+                })
+            }
+            None => Ok(Self::Key {
+                project: None,
+                key: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{id}"),
+            Self::Key {
+                project: Some(project),
+                key,
+            } => write!(f, "{project}/{key}"),
+            Self::Key { project: None, key } => write!(f, "{key}"),
+            Self::Project(project_id) => write!(f, "project:{project_id}"),
+        }
+    }
+}
+
+/// The result of resolving secret references to UUIDs.
+#[derive(Debug)]
+pub struct Resolved {
+    pub mappings: HashMap<Uuid, Vec<SecretMapping>>,
+    /// Optional and default-valued mappings whose key or project does not exist.
+    pub unresolved: Vec<SecretMapping>,
+}
+
+/// Resolves every key and project reference to secret UUIDs using the list APIs. UUID references
+/// are passed through untouched, so no list requests are made unless at least one other kind of
+/// reference is present.
+///
+/// A secret can be mapped to several names, including through several project references, but
+/// two different secrets can never be mapped to the same name.
+///
+/// Each list request is subject to the request timeout and the overall deadline.
+pub async fn resolve_secret_refs(
+    client: &Client,
+    refs: HashMap<SecretRef, Vec<SecretMapping>>,
+    timeouts: Timeouts,
+    api_url: &str,
+) -> Result<Resolved> {
+    let mut resolved = Resolved {
+        mappings: HashMap::with_capacity(refs.len()),
+        unresolved: Vec::new(),
+    };
+    let mut keys: Vec<(Option<String>, String, Vec<SecretMapping>)> = Vec::new();
+    let mut project_patterns: Vec<(Uuid, SecretMapping)> = Vec::new();
+
+    for (secret_ref, mappings) in refs {
+        match secret_ref {
+            SecretRef::Id(id) => {
+                for mapping in mappings {
+                    insert_unique(&mut resolved.mappings, id, mapping);
+                }
+            }
+            SecretRef::Key { project, key } => keys.push((project, key, mappings)),
+            SecretRef::Project(project_id) => {
+                project_patterns.extend(mappings.into_iter().map(|mapping| (project_id, mapping)))
+            }
+        }
+    }
+
+    let mut project_secrets: HashMap<Uuid, Vec<(Uuid, String)>> = HashMap::new();
+
+    if !keys.is_empty() {
+        let organization_id = client
+            .internal
+            .get_access_token_organization()
+            .ok_or_else(|| {
+                anyhow::anyhow!("Could not determine the organization of the access token")
+            })?;
+
+        let mut organization_secrets: Option<Vec<(Uuid, String)>> = None;
+        let mut projects: Option<Vec<(Uuid, String)>> = None;
+
+        for (project, key, mappings) in keys {
+            let id = match project {
+                None => {
+                    if organization_secrets.is_none() {
+                        debug!("Listing secrets in organization {organization_id}");
+                        let secrets = timeouts
+                            .request(PHASE, api_url, async {
+                                client
+                                    .secrets()
+                                    .list(&SecretIdentifiersRequest { organization_id })
+                                    .await
+                                    .map_err(|e| {
+                                        anyhow::anyhow!("Failed to list secrets.\nError: {e}")
+                                    })
+                            })
+                            .await?;
+                        organization_secrets =
+                            Some(secrets.data.into_iter().map(|s| (s.id, s.key)).collect());
+                    }
+
+                    find_unique_key(organization_secrets.as_deref().unwrap_or_default(), &key)
+                        .map_err(|e| anyhow::anyhow!("{e} in the organization"))?
+                        .ok_or_else(|| format!("'{key}' was not found in the organization"))
+                }
+                Some(project) => {
+                    let project_id = match Uuid::from_str(&project) {
+                        Ok(project_id) => Some(project_id),
+                        Err(_) => {
+                            if projects.is_none() {
+                                debug!("Listing projects in organization {organization_id}");
+                                let response = timeouts
+                                    .request(PHASE, api_url, async {
+                                        client
+                                            .projects()
+                                            .list(&ProjectsListRequest { organization_id })
+                                            .await
+                                            .map_err(|e| {
+                                                anyhow::anyhow!(
+                                                    "Failed to list projects.\nError: {e}"
+                                                )
+                                            })
+                                    })
+                                    .await?;
+                                projects = Some(
+                                    response.data.into_iter().map(|p| (p.id, p.name)).collect(),
+                                );
+                            }
+
+                            find_unique_key(projects.as_deref().unwrap_or_default(), &project)
+                                .map_err(|e| anyhow::anyhow!("Project {e}"))?
+                        }
+                    };
+
+                    match project_id {
+                        Some(project_id) => {
+                            let secrets = list_project_secrets(
+                                client,
+                                &mut project_secrets,
+                                project_id,
+                                timeouts,
+                                api_url,
+                            )
+                            .await?;
+                            find_unique_key(secrets, &key)
+                                .map_err(|e| anyhow::anyhow!("{e} in project {project}"))?
+                                .ok_or_else(|| {
+                                    format!("'{key}' was not found in project {project}")
+                                })
+                        }
+                        None => Err(format!("Project '{project}' was not found")),
+                    }
+                }
+            };
+
+            match id {
+                Ok(id) => {
+                    debug!("Resolved secret key '{key}' to {id}");
+                    for mapping in mappings {
+                        insert_unique(&mut resolved.mappings, id, mapping);
+                    }
+                }
+                Err(message) if mappings.iter().any(SecretMapping::is_required) => {
+                    bail!("{message}")
+                }
+                Err(message) => {
+                    for mapping in mappings {
+                        debug!("{message}; falling back for {}", mapping.name);
+                        resolved.unresolved.push(mapping);
+                    }
+                }
+            }
+        }
+    }
+
+    for (project_id, pattern) in project_patterns {
+        let Some(prefix) = pattern.name.strip_suffix('*') else {
+            bail!(
+                "Secrets from project:{project_id} must be mapped to a name ending in '*', like 'PREFIX_*'"
+            );
+        };
+
+        let secrets =
+            list_project_secrets(client, &mut project_secrets, project_id, timeouts, api_url)
+                .await?;
+        map_project_secrets(
+            &mut resolved.mappings,
+            project_id,
+            prefix,
+            &pattern,
+            secrets,
+        )?;
+    }
+
+    check_unique_names(&resolved)?;
+
+    Ok(resolved)
+}
+
+/// Maps every secret in a project to the prefix followed by its key, in addition to any other
+/// names the secret is mapped to.
+fn map_project_secrets(
+    mappings: &mut HashMap<Uuid, Vec<SecretMapping>>,
+    project_id: Uuid,
+    prefix: &str,
+    pattern: &SecretMapping,
+    secrets: &[(Uuid, String)],
+) -> Result<()> {
+    let mut names: HashMap<String, &str> = HashMap::with_capacity(secrets.len());
+
+    for (id, key) in secrets {
+        let name = env_var_name(prefix, key);
+        if let Some(other_key) = names.insert(name.clone(), key) {
+            bail!(
+                "Secret keys '{other_key}' and '{key}' in project {project_id} both map to {name}"
+            );
+        }
+
+        let secret_mappings = mappings.entry(*id).or_default();
+        if secret_mappings.iter().any(|mapping| mapping.name == name) {
+            debug!("Secret {id} in project {project_id} is already mapped to {name} explicitly");
+            continue;
+        }
+
+        debug!("Mapping secret '{key}' in project {project_id} to {name}");
+        secret_mappings.push(SecretMapping {
+            name,
+            fallback: pattern.fallback.clone(),
+            selector: None,
+            transforms: pattern.transforms.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Fails if two different secrets are mapped to the same name, since which value is set would
+/// then depend on the order the server returns them in.
+fn check_unique_names(resolved: &Resolved) -> Result<()> {
+    let mut names: HashMap<&str, Option<Uuid>> = HashMap::new();
+
+    let mapped = resolved
+        .mappings
+        .iter()
+        .flat_map(|(id, mappings)| mappings.iter().map(move |mapping| (Some(*id), mapping)))
+        .chain(resolved.unresolved.iter().map(|mapping| (None, mapping)));
+
+    for (id, mapping) in mapped {
+        let Some(other) = names.insert(&mapping.name, id) else {
+            continue;
+        };
+
+        match (other, id) {
+            (Some(a), Some(b)) => {
+                let (first, second) = (a.min(b), a.max(b));
+                bail!(
+                    "Secrets {first} and {second} are both mapped to {}",
+                    mapping.name
+                )
+            }
+            _ => bail!("{} is mapped to more than one secret", mapping.name),
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the secrets in a project once, caching the identifiers for later references.
+async fn list_project_secrets<'a>(
+    client: &Client,
+    cache: &'a mut HashMap<Uuid, Vec<(Uuid, String)>>,
+    project_id: Uuid,
+    timeouts: Timeouts,
+    api_url: &str,
+) -> Result<&'a [(Uuid, String)]> {
+    if !cache.contains_key(&project_id) {
+        debug!("Listing secrets in project {project_id}");
+        let secrets = timeouts
+            .request(PHASE, api_url, async {
+                client
+                    .secrets()
+                    .list_by_project(&SecretIdentifiersByProjectRequest { project_id })
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "Failed to list secrets in project {project_id}.\nError: {e}"
+                        )
+                    })
+            })
+            .await?;
+        cache.insert(
+            project_id,
+            secrets.data.into_iter().map(|s| (s.id, s.key)).collect(),
+        );
+    }
+
+    Ok(&cache[&project_id])
+}
+
+/// Turns a secret key into an environment variable name: ASCII letters are uppercased, anything
+/// other than letters and digits becomes `_`, and a leading digit is prefixed with `_`.
+pub fn env_var_name(prefix: &str, key: &str) -> String {
+    let mut name: String = prefix
+        .chars()
+        .chain(key.chars())
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+
+    name
+}
+
+/// Adds a mapping for the secret, replacing an earlier mapping of the same secret to the same
+/// name.
+fn insert_unique(map: &mut HashMap<Uuid, Vec<SecretMapping>>, id: Uuid, mapping: SecretMapping) {
+    let mappings = map.entry(id).or_default();
+    match mappings
+        .iter_mut()
+        .find(|old_value| old_value.name == mapping.name)
+    {
+        Some(old_value) => {
+            error::warning(&format!(
+                "Duplicate UUID found: {id} > {}. The last mapping is used",
+                mapping.name
+            ));
+            *old_value = mapping;
+        }
+        None => mappings.push(mapping),
+    }
+}
+
+/// Finds the single entry named `key`, if any. More than one match is an error, since guessing
+/// which secret was meant could export the wrong value.
+fn find_unique_key(entries: &[(Uuid, String)], key: &str) -> Result<Option<Uuid>> {
+    let mut matches = entries.iter().filter(|(_, k)| k == key).map(|(id, _)| *id);
+
+    match (matches.next(), matches.next()) {
+        (Some(id), None) => Ok(Some(id)),
+        (None, _) => Ok(None),
+        (Some(first), Some(second)) => {
+            let ids: Vec<String> = [first, second]
+                .into_iter()
+                .chain(matches)
+                .map(|id| id.to_string())
+                .collect();
+            bail!("'{key}' is ambiguous, matching {}", ids.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_ref_from_str() {
+        assert_eq!(
+            SecretRef::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap(),
+            SecretRef::Id(Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap())
+        );
+        assert_eq!(
+            SecretRef::from_str("prod-db/password").unwrap(),
+            SecretRef::Key {
+                project: Some("prod-db".to_string()),
+                key: "password".to_string()
+            }
+        );
+        assert_eq!(
+            SecretRef::from_str("password").unwrap(),
+            SecretRef::Key {
+                project: None,
+                key: "password".to_string()
+            }
+        );
+        assert_eq!(
+            SecretRef::from_str("project:bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap(),
+            SecretRef::Project(Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap())
+        );
+        assert!(SecretRef::from_str("").is_err());
+        assert!(SecretRef::from_str("project:not-a-uuid").is_err());
+        assert!(SecretRef::from_str("prod-db/").is_err());
+    }
+
+    #[test]
+    fn test_find_unique_key() {
+        let one = Uuid::new_v4();
+        let two = Uuid::new_v4();
+        let entries = vec![
+            (one, "password".to_string()),
+            (two, "username".to_string()),
+            (Uuid::new_v4(), "token".to_string()),
+            (Uuid::new_v4(), "token".to_string()),
+        ];
+
+        assert_eq!(find_unique_key(&entries, "password").unwrap(), Some(one));
+        assert_eq!(find_unique_key(&entries, "username").unwrap(), Some(two));
+        assert_eq!(find_unique_key(&entries, "missing").unwrap(), None);
+        assert!(
+            find_unique_key(&entries, "token")
+                .unwrap_err()
+                .to_string()
+                .contains("ambiguous")
+        );
+    }
+
+    fn names(mappings: &HashMap<Uuid, Vec<SecretMapping>>, id: Uuid) -> Vec<&str> {
+        mappings[&id]
+            .iter()
+            .map(|mapping| mapping.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_map_project_secrets() {
+        let project_id = Uuid::new_v4();
+        let db = Uuid::new_v4();
+        let token = Uuid::new_v4();
+        let secrets = vec![(db, "db".to_string()), (token, "token".to_string())];
+
+        let mut mappings = HashMap::from([(db, vec![SecretMapping::new("APP_DB")])]);
+        let app = SecretMapping::new("APP_*");
+        let ci = SecretMapping::new("CI_*");
+        map_project_secrets(&mut mappings, project_id, "APP_", &app, &secrets).unwrap();
+        map_project_secrets(&mut mappings, project_id, "CI_", &ci, &secrets).unwrap();
+
+        assert_eq!(names(&mappings, db), vec!["APP_DB", "CI_DB"]);
+        assert_eq!(names(&mappings, token), vec!["APP_TOKEN", "CI_TOKEN"]);
+
+        let colliding = vec![(db, "db".to_string()), (token, "DB".to_string())];
+        let mut mappings = HashMap::new();
+        assert!(map_project_secrets(&mut mappings, project_id, "APP_", &app, &colliding).is_err());
+    }
+
+    #[test]
+    fn test_check_unique_names() {
+        let explicit = Uuid::new_v4();
+        let from_project = Uuid::new_v4();
+
+        let resolved = Resolved {
+            mappings: HashMap::from([
+                (explicit, vec![SecretMapping::new("APP_DB")]),
+                (
+                    from_project,
+                    vec![
+                        SecretMapping::new("APP_TOKEN"),
+                        SecretMapping::new("CI_TOKEN"),
+                    ],
+                ),
+            ]),
+            unresolved: vec![SecretMapping::new("LOG_LEVEL")],
+        };
+        assert!(check_unique_names(&resolved).is_ok());
+
+        let resolved = Resolved {
+            mappings: HashMap::from([
+                (explicit, vec![SecretMapping::new("APP_DB")]),
+                (from_project, vec![SecretMapping::new("APP_DB")]),
+            ]),
+            unresolved: Vec::new(),
+        };
+        let error = check_unique_names(&resolved).unwrap_err().to_string();
+        assert!(error.contains("APP_DB"), "{error}");
+
+        let resolved = Resolved {
+            mappings: HashMap::from([(explicit, vec![SecretMapping::new("LOG_LEVEL")])]),
+            unresolved: vec![SecretMapping::new("LOG_LEVEL")],
+        };
+        assert!(check_unique_names(&resolved).is_err());
+    }
+
+    #[test]
+    fn test_env_var_name() {
+        assert_eq!(env_var_name("", "db-password"), "DB_PASSWORD");
+        assert_eq!(env_var_name("APP_", "smtp.host"), "APP_SMTP_HOST");
+        assert_eq!(env_var_name("", "2fa seed"), "_2FA_SEED");
+        assert_eq!(env_var_name("APP_", "2fa"), "APP_2FA");
+    }
+}
@@ -1,112 +1,590 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bitwarden_core::auth::login::AccessTokenLoginRequest;
 use bitwarden_core::{Client, ClientSettings};
 use bitwarden_sm::ClientSecretsExt;
-use bitwarden_sm::secrets::SecretsGetRequest;
-
-use config::{Config, get_env, infer_urls};
+use bitwarden_sm::secrets::{SecretGetRequest, SecretResponse, SecretsGetRequest};
+
+use config::{Account, Config, DEFAULT_ACCOUNT, get_env};
+use error::ActionError;
+use lookup::{SecretRef, resolve_secret_refs};
+use mapping::{Fallback, SecretMapping};
+use report::RunReport;
+use retry::retry;
+use summary::Source;
+use timeout::Timeouts;
 use uuid::Uuid;
 
 mod config;
+mod doctor;
+mod error;
+mod exec;
+mod lookup;
+mod mapping;
+mod mask;
+mod names;
+mod oidc;
+mod output;
+mod proxy;
+mod report;
+mod retry;
+mod selector;
+mod summary;
+mod timeout;
+mod tls;
+mod transform;
+
+fn main() {
+    let mut run_report = RunReport::default();
+    let result = run(&mut run_report);
+
+    if let Err(e) = run_report.write(&result) {
+        error::warning(&format!("Failed to write the run report.\nError: {e}"));
+    }
+
+    match result {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(e) => std::process::exit(error::report(&e)),
+    }
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
+/// Runs the action and returns the exit code, which is the command's in exec mode.
+///
+/// The inputs are read, and the environment the SDK's HTTP client reads is set up, before the
+/// async runtime starts any threads.
+fn run(run_report: &mut RunReport) -> Result<i32> {
     // --test arg to validate the binaries in CI
-    if std::env::args().any(|arg| arg == "--test") {
+    if is_self_test(std::env::args()) {
         println!("success");
-        return Ok(());
+        return Ok(0);
     }
 
+    // doctor mode: `sm-action doctor` checks the inputs and connectivity without fetching secrets
+    if std::env::args().nth(1).as_deref() == Some("doctor") {
+        return doctor::run();
+    }
+
+    // read before the other inputs, so that invalid inputs are reported too
+    run_report.path = get_env("INPUT_REPORT_FILE");
+
+    // exec mode: `sm-action exec -- <cmd>` runs <cmd> with the secrets in its environment only
+    let exec_command = exec::command_from_args(std::env::args())?;
+
     let config = Config::new()?;
-    let (api_url, identity_url) = infer_urls(&config)?;
+    proxy::configure(&config)?;
+    tls::configure(&config)?;
 
-    let client = Client::new(Some(ClientSettings {
-        identity_url,
+    runtime()?.block_on(fetch_and_set(config, exec_command, run_report))
+}
+
+/// Returns whether the binary was started as `sm-action --test`. Only the first argument counts,
+/// so that `--test` can be passed to a command run in exec mode.
+fn is_self_test(args: impl IntoIterator<Item = String>) -> bool {
+    args.into_iter().nth(1).as_deref() == Some("--test")
+}
+
+/// Builds the async runtime. Its worker threads are started here, so the environment must not be
+/// changed after this is called.
+fn runtime() -> Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to start the async runtime.\nError: {e}"))
+}
+
+/// Fetches the secrets for every account, then sets them, writes them out or runs the command
+/// with them in its environment.
+async fn fetch_and_set(
+    config: Config,
+    exec_command: Option<Vec<String>>,
+    run_report: &mut RunReport,
+) -> Result<i32> {
+    let accounts = config.all_accounts()?;
+
+    println!("Parsing secrets input...");
+    let mut requests: Vec<(Account, HashMap<SecretRef, Vec<SecretMapping>>)> =
+        Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let ref_to_mapping = parse_secret_input(account.secrets.clone()).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to parse secrets input. Ensure the format is 'UUID > Name' or 'key > Name'.\nError: {e}"
+            )
+        })?;
+        requests.push((account, ref_to_mapping));
+    }
+
+    // the deadline covers every request the action makes, the token exchange included
+    let timeouts = Timeouts::start(config.request_timeout, config.timeout);
+
+    if let Some(exchange_url) = config.oidc_exchange_url.as_deref()
+        && let Some((account, _)) = requests
+            .iter_mut()
+            .find(|(account, _)| account.name == DEFAULT_ACCOUNT)
+    {
+        println!("Exchanging GitHub OIDC token...");
+        let start = Instant::now();
+        let http = tls::http_client(&config)?;
+        account.access_token = timeouts
+            .requests(
+                "GitHub OIDC token exchange",
+                exchange_url,
+                oidc::access_token(
+                    &http,
+                    exchange_url,
+                    config.oidc_audience.as_deref(),
+                    config.max_retries,
+                    timeouts,
+                ),
+            )
+            .await?;
+        run_report.time("oidc", start.elapsed());
+    }
+
+    for (account, _) in requests.iter() {
+        run_report.servers.push((
+            account.name.clone(),
+            account.api_url.clone(),
+            account.identity_url.clone(),
+        ));
+    }
+
+    // each account logs in and fetches with its own client, concurrently
+    let multiple_accounts = requests.len() > 1;
+    let config = &config;
+    let requested_ids = &Mutex::new(Vec::new());
+    let fetched = futures::future::try_join_all(requests.into_iter().map(
+        |(account, ref_to_mapping)| async move {
+            let name = account.name.clone();
+            let result =
+                fetch_account(account, ref_to_mapping, config, timeouts, requested_ids).await;
+            if multiple_accounts {
+                result.with_context(|| format!("Failed to fetch secrets for account '{name}'"))
+            } else {
+                result
+            }
+        },
+    ))
+    .await;
+
+    // recorded before fetching, so that a failed fetch still reports what was requested
+    run_report.requested_ids =
+        std::mem::take(&mut *requested_ids.lock().unwrap_or_else(PoisonError::into_inner));
+    let fetched = fetched?;
+
+    let mut values: BTreeMap<String, (String, Source)> = BTreeMap::new();
+    let mut value_accounts: HashMap<String, String> = HashMap::new();
+
+    for account in fetched {
+        run_report.returned_ids.extend(account.returned_ids);
+        for (phase, elapsed) in account.timings {
+            run_report.time(&phase, elapsed);
+        }
+
+        for (name, value, source) in account.values {
+            if let Some(other) = value_accounts.insert(name.clone(), account.name.clone()) {
+                anyhow::bail!(
+                    "{name} is set by secrets from both account '{other}' and account '{}'",
+                    account.name
+                );
+            }
+            values.insert(name, (value, source));
+        }
+    }
+
+    let name_to_value_map: BTreeMap<&str, &str> = values
+        .iter()
+        .map(|(name, (value, _))| (name.as_str(), value.as_str()))
+        .collect();
+
+    run_report.names = name_to_value_map
+        .keys()
+        .map(|name| name.to_string())
+        .collect();
+
+    names::check_reserved(
+        name_to_value_map.keys().copied(),
+        &config.allow_reserved_names,
+    )?;
+
+    if let Some(command) = exec_command {
+        let env: HashMap<String, String> = name_to_value_map
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        println!("Running command with secrets in its environment...");
+        run_report.sinks.push("exec".to_string());
+        return exec::run(&command, env).await;
+    }
+
+    println!("Setting secrets...");
+    for (name, value) in name_to_value_map.iter() {
+        set_secrets(name, value, config.set_env)?;
+    }
+    if config.set_env {
+        run_report.sinks.push("env".to_string());
+    }
+    run_report.sinks.push("output".to_string());
+
+    if let Some(output_file) = config.output_file.as_deref() {
+        println!("Writing secrets to {output_file}...");
+        output::write_secrets_file(output_file, config.output_format, &name_to_value_map)?;
+        run_report.sinks.push(format!("file:{output_file}"));
+    }
+
+    if let Some(output_dir) = config.output_dir.as_deref() {
+        println!("Writing secrets to files in {output_dir}...");
+        output::write_secret_files(output_dir, &name_to_value_map)?;
+        run_report.sinks.push(format!("dir:{output_dir}"));
+    }
+
+    println!("Completed setting secrets.");
+
+    let summary_rows: Vec<(&str, Source)> = values
+        .iter()
+        .map(|(name, (_, source))| (name.as_str(), *source))
+        .collect();
+    if let Err(e) = summary::write_step_summary(&summary_rows, config.set_env) {
+        error::warning(&format!("Failed to write the job summary.\nError: {e}"));
+    }
+
+    Ok(0)
+}
+
+/// The secrets fetched with one account's access token, and what the run report needs to know
+/// about them.
+struct FetchedAccount {
+    name: String,
+    values: Vec<(String, String, Source)>,
+    returned_ids: Vec<Uuid>,
+    timings: Vec<(String, Duration)>,
+}
+
+/// Logs in with the account's access token, resolves its secret references and fetches them.
+/// Returns the value for each name, after selectors, fallbacks and transforms are applied.
+///
+/// The resolved UUIDs are added to `requested_ids` before they are fetched.
+async fn fetch_account(
+    account: Account,
+    ref_to_mapping: HashMap<SecretRef, Vec<SecretMapping>>,
+    config: &Config,
+    timeouts: Timeouts,
+    requested_ids: &Mutex<Vec<Uuid>>,
+) -> Result<FetchedAccount> {
+    let Account {
+        name: account_name,
+        access_token,
         api_url,
+        identity_url,
+        ..
+    } = account;
+
+    let phase = |phase: &str| match account_name.as_str() {
+        DEFAULT_ACCOUNT => phase.to_string(),
+        name => format!("{name}.{phase}"),
+    };
+    let mut timings: Vec<(String, Duration)> = Vec::new();
+
+    let client = Client::new(Some(ClientSettings {
+        identity_url: identity_url.clone(),
+        api_url: api_url.clone(),
         user_agent: "bitwarden/sm-action".to_string(),
         device_type: bitwarden_core::DeviceType::SDK,
     }));
 
-    println!("Parsing secrets input...");
-    let id_to_name_map = parse_secret_input(config.secrets).map_err(|_| {
-        anyhow::anyhow!("Failed to parse secrets input. Ensure the format is 'UUID > Name'.")
-    })?;
-
     println!("Authenticating with Bitwarden...");
-    let auth_result = client
-        .auth()
-        .login_access_token(&AccessTokenLoginRequest {
-            access_token: config.access_token,
-            state_file: None,
+    let login_request = AccessTokenLoginRequest {
+        access_token,
+        state_file: None,
+    };
+    let start = Instant::now();
+    let auth_result = retry(config.max_retries, timeouts, "Authentication", || {
+        timeouts.request("Authentication", &identity_url, async {
+            client
+                .auth()
+                .login_access_token(&login_request)
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))
         })
-        .await;
+    })
+    .await;
+
+    timings.push((phase("login"), start.elapsed()));
 
     if let Err(e) = auth_result {
-        return Err(anyhow::anyhow!(
-            "Authentication with Bitwarden failed.\nError: {}",
-            e.to_string()
-        ));
+        if e.is::<ActionError>() {
+            return Err(e);
+        }
+        return Err(ActionError::from_login(&e, &identity_url).into());
     }
 
-    let secret_ids: Vec<Uuid> = id_to_name_map.keys().cloned().collect();
+    let start = Instant::now();
+    let resolved = resolve_secret_refs(&client, ref_to_mapping, timeouts, &api_url).await?;
+    timings.push((phase("resolve"), start.elapsed()));
+    let mappings = resolved.mappings;
+
+    let secret_ids: Vec<Uuid> = mappings.keys().cloned().collect();
+    requested_ids
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .extend(&secret_ids);
+
+    let start = Instant::now();
+    let secrets = match fetch_secrets(&client, &secret_ids, config.max_retries, timeouts, &api_url)
+        .await
+    {
+        Ok(secrets) => secrets,
+        Err(e) => {
+            if e.is::<ActionError>() {
+                return Err(e);
+            }
+            if let Some(action_error) = ActionError::from_fetch(&e, &api_url) {
+                return Err(action_error.into());
+            }
+
+            if !error::is_not_found(&e) {
+                return Err(anyhow::anyhow!(
+                    "The secrets provided could not be found. Please check the machine account has access to the secret UUIDs provided.\nError: {}",
+                    e.to_string()
+                ));
+            }
+
+            // a request for several secrets fails as a whole if any of them is missing, so fetch
+            // them one at a time; whether the missing ones are acceptable is decided below
+            fetch_each_secret(&client, &secret_ids, config.max_retries, timeouts, &api_url).await?
+        }
+    };
+
+    timings.push((phase("fetch"), start.elapsed()));
+
+    let returned_ids: HashSet<Uuid> = secrets.iter().map(|secret| secret.id).collect();
+    let mut required_missing: Vec<(Uuid, String)> = Vec::new();
+    let mut fallbacks: Vec<&SecretMapping> = resolved.unresolved.iter().collect();
+
+    for (id, mapping) in missing_secrets(&mappings, &returned_ids) {
+        match mapping.fallback {
+            Fallback::Required => required_missing.push((id, mapping.name.clone())),
+            Fallback::Optional => debug!("Optional secret {id} > {} was not found", mapping.name),
+            Fallback::Default(_) => fallbacks.push(mapping),
+        }
+    }
+
+    if !required_missing.is_empty() {
+        let missing_error = ActionError::SecretsNotFound(required_missing);
+        if !config.allow_missing {
+            return Err(missing_error.into());
+        }
+        error::warning(&missing_error.to_string());
+    }
+
+    // masks are registered as soon as the values are known, for every form they take, since the
+    // transforms may set something else entirely, such as the path of a file
+    let mask_secret = |value: &str| {
+        mask::mask_value(value);
+        if config.mask_encodings {
+            mask::mask_encodings(value);
+        }
+    };
+
+    let mut raw_values: Vec<(&SecretMapping, Source, String)> = Vec::new();
+
+    for secret in secrets.iter() {
+        mask_secret(&secret.value);
+        for mapping in mappings.get(&secret.id).into_iter().flatten() {
+            let value = match &mapping.selector {
+                None => secret.value.clone(),
+                Some(selector) => match selector.select(&secret.value) {
+                    Ok(value) => value,
+                    Err(e) if mapping.is_required() => {
+                        return Err(anyhow::anyhow!(
+                            "Failed to extract {selector} from secret {} for {}.\nError: {e}",
+                            secret.id,
+                            mapping.name
+                        ));
+                    }
+                    Err(e) => {
+                        debug!("Failed to extract {selector} for {}: {e}", mapping.name);
+                        fallbacks.push(mapping);
+                        continue;
+                    }
+                },
+            };
+            let source = Source::Secret {
+                id: secret.id,
+                project_id: secret.project_id,
+            };
+            raw_values.push((mapping, source, value));
+        }
+    }
+
+    for mapping in fallbacks {
+        if let Fallback::Default(default) = &mapping.fallback {
+            debug!("Using the default value for {}", mapping.name);
+            raw_values.push((mapping, Source::Default, default.clone()));
+        }
+    }
 
-    let secrets = client
-        .secrets()
-        .get_by_ids(SecretsGetRequest { ids: secret_ids })
-        .await.map_err(|e| {
+    let mut values: BTreeMap<String, (String, Source)> = BTreeMap::new();
+    for (mapping, source, value) in raw_values {
+        // defaults are written in the workflow, so neither they nor their transforms are secret
+        let value = match source {
+            Source::Default => transform::apply(&mapping.transforms, value, |_| {}),
+            _ => transform::apply(&mapping.transforms, value, mask_secret),
+        };
+        let value = value.map_err(|e| {
             anyhow::anyhow!(
-                "The secrets provided could not be found. Please check the machine account has access to the secret UUIDs provided.\nError: {}",
-                e.to_string()
+                "Failed to transform the value for {}.\nError: {e}",
+                mapping.name
             )
         })?;
+        values.insert(mapping.name.clone(), (value, source));
+    }
 
-    println!("Setting secrets...");
-    for secret in secrets.data.iter() {
-        id_to_name_map
-            .get(&secret.id)
-            .map(|name| set_secrets(name, &secret.value, config.set_env))
-            .transpose()?;
+    Ok(FetchedAccount {
+        name: account_name,
+        values: values
+            .into_iter()
+            .map(|(name, (value, source))| (name, value, source))
+            .collect(),
+        returned_ids: returned_ids.into_iter().collect(),
+        timings,
+    })
+}
+
+/// Fetches the secrets by UUID, retrying transient failures and requests that time out.
+async fn fetch_secrets(
+    client: &Client,
+    ids: &[Uuid],
+    max_retries: u32,
+    timeouts: Timeouts,
+    api_url: &str,
+) -> Result<Vec<SecretResponse>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
     }
 
-    println!("Completed setting secrets.");
+    retry(max_retries, timeouts, "Fetching secrets", || {
+        timeouts.request("Fetching secrets", api_url, async {
+            client
+                .secrets()
+                .get_by_ids(SecretsGetRequest { ids: ids.to_vec() })
+                .await
+                .map(|response| response.data)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+        })
+    })
+    .await
+}
 
-    Ok(())
+/// Returns the mappings of requested secrets that the server did not return, sorted by UUID.
+fn missing_secrets<'a>(
+    mappings: &'a HashMap<Uuid, Vec<SecretMapping>>,
+    returned_ids: &HashSet<Uuid>,
+) -> Vec<(Uuid, &'a SecretMapping)> {
+    let mut missing: Vec<(Uuid, &SecretMapping)> = mappings
+        .iter()
+        .filter(|(id, _)| !returned_ids.contains(*id))
+        .flat_map(|(id, mappings)| mappings.iter().map(|mapping| (*id, mapping)))
+        .collect();
+    missing.sort_by(|(a_id, a), (b_id, b)| a_id.cmp(b_id).then_with(|| a.name.cmp(&b.name)));
+    missing
+}
+
+/// Fetches the secrets one at a time, since a request for several secrets fails as a whole if
+/// any of them is missing. Returns the secrets that were found; the missing ones are left out.
+async fn fetch_each_secret(
+    client: &Client,
+    ids: &[Uuid],
+    max_retries: u32,
+    timeouts: Timeouts,
+    api_url: &str,
+) -> Result<Vec<SecretResponse>> {
+    let mut secrets = Vec::with_capacity(ids.len());
+
+    for id in ids {
+        debug!("Fetching secret {id} on its own");
+        let result = retry(
+            max_retries,
+            timeouts,
+            "Checking for missing secrets",
+            || {
+                timeouts.request("Checking for missing secrets", api_url, async {
+                    client
+                        .secrets()
+                        .get(&SecretGetRequest { id: *id })
+                        .await
+                        .map_err(|e| anyhow::anyhow!("{e}"))
+                })
+            },
+        )
+        .await;
+
+        match result {
+            Ok(secret) => secrets.push(secret),
+            Err(e) if e.is::<ActionError>() => return Err(e),
+            Err(e) if error::is_not_found(&e) => debug!("Secret {id} was not found"),
+            Err(e) => {
+                return Err(match ActionError::from_fetch(&e, api_url) {
+                    Some(action_error) => action_error.into(),
+                    None => e,
+                });
+            }
+        }
+    }
+
+    Ok(secrets)
 }
 
 /// Parses the secret input from the GitHub Actions environment variable.
-fn parse_secret_input(secret_lines: Vec<String>) -> Result<HashMap<Uuid, String>> {
-    let mut map: HashMap<Uuid, String> = HashMap::with_capacity(secret_lines.capacity());
+/// The left-hand side of each line is either a secret UUID, a secret key optionally prefixed
+/// with a project name or UUID (`project/key`), or a whole project (`project:UUID > PREFIX_*`).
+/// A JSON-valued secret may be mapped several times with different selectors
+/// (`UUID#.credentials.client_id > CLIENT_ID`). See `mapping::parse_line` for the full grammar.
+fn parse_secret_input(secret_lines: Vec<String>) -> Result<HashMap<SecretRef, Vec<SecretMapping>>> {
+    let mut map: HashMap<SecretRef, Vec<SecretMapping>> =
+        HashMap::with_capacity(secret_lines.capacity());
 
     for line in secret_lines.iter() {
         debug!("Parsing line: {line}");
-        let uuid_part = line.split('>').next().unwrap_or_default().trim();
-        let uuid = Uuid::from_str(uuid_part)
-            .map_err(|_| anyhow::anyhow!("Invalid UUID format: {uuid_part}"))?;
-
-        let desired_name = line.split('>').nth(1).unwrap_or_default().trim();
-
-        if let Some(old_value) = map.insert(uuid, desired_name.to_string()) {
-            eprintln!(
-                "Warning: Duplicate UUID found: {uuid}. Old value: {old_value}, New value: {desired_name}"
-            );
+        let (secret_ref, mapping) = mapping::parse_line(line)?;
+        let mappings = map.entry(secret_ref.clone()).or_default();
+
+        // a secret can be mapped to several names, but each name only once
+        match mappings
+            .iter_mut()
+            .find(|old_value| old_value.name == mapping.name)
+        {
+            Some(old_value) => {
+                error::warning(&format!(
+                    "Duplicate secret found: {secret_ref} > {}. The last mapping is used",
+                    mapping.name
+                ));
+                *old_value = mapping;
+            }
+            None => mappings.push(mapping),
         }
     }
 
     Ok(map)
 }
 
-/// Masks a value in the GitHub Actions logs to prevent it from being displayed.
-fn mask_value(value: &str) {
-    println!("::add-mask::{value}");
-}
+/// How many random heredoc delimiters to try before giving up on a value that contains them.
+const MAX_DELIMITER_ATTEMPTS: usize = 5;
 
 fn issue_file_command(mut file: std::fs::File, key: &str, value: &str) -> Result<()> {
-    let delimiter = format!("ghadelimiter_{}", uuid::Uuid::new_v4());
+    // the runner reads `KEY<<DELIMITER` or `KEY=VALUE` per line, so any of these in a key would
+    // let it inject other variables
+    if key.is_empty() || key.contains(|c: char| c.is_control() || c == '=') || key.contains("<<") {
+        anyhow::bail!("'{}' cannot be used as a variable name", key.escape_debug());
+    }
+
+    let delimiter = heredoc_delimiter(value, || format!("ghadelimiter_{}", Uuid::new_v4()))?;
     writeln!(file, "{key}<<{delimiter}")?;
     writeln!(file, "{value}")?;
     writeln!(file, "{delimiter}")?;
@@ -114,10 +592,22 @@ fn issue_file_command(mut file: std::fs::File, key: &str, value: &str) -> Result
     Ok(())
 }
 
-/// Sets a secret in the GitHub Actions environment.
-fn set_secrets(secret_name: &str, secret_value: &str, set_env: bool) -> Result<()> {
-    mask_value(secret_value);
+/// Returns a delimiter that does not occur in the value, so that the value cannot end the
+/// heredoc early and inject other variables.
+fn heredoc_delimiter(value: &str, mut generate: impl FnMut() -> String) -> Result<String> {
+    for _ in 0..MAX_DELIMITER_ATTEMPTS {
+        let delimiter = generate();
+        if !value.contains(&delimiter) {
+            return Ok(delimiter);
+        }
+        debug!("The value contains the heredoc delimiter; generating a new one");
+    }
+
+    anyhow::bail!("Could not generate a heredoc delimiter that does not occur in the value")
+}
 
+/// Sets a secret in the GitHub Actions environment. The value must have been masked already.
+fn set_secrets(secret_name: &str, secret_value: &str, set_env: bool) -> Result<()> {
     if set_env {
         let env_path = get_env("GITHUB_ENV").unwrap_or("/dev/null".to_owned());
         debug!("Writing to GITHUB_ENV: {env_path}");
@@ -145,8 +635,36 @@ fn set_secrets(secret_name: &str, secret_value: &str, set_env: bool) -> Result<(
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use super::*;
 
+    fn names<'a>(
+        map: &'a HashMap<SecretRef, Vec<SecretMapping>>,
+        secret_ref: &SecretRef,
+    ) -> Vec<&'a str> {
+        map.get(secret_ref)
+            .into_iter()
+            .flatten()
+            .map(|mapping| mapping.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_is_self_test() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert!(is_self_test(args(&["sm-action", "--test"])));
+        assert!(!is_self_test(args(&["sm-action"])));
+        assert!(!is_self_test(args(&[
+            "sm-action",
+            "exec",
+            "--",
+            "./ci.sh",
+            "--test"
+        ])));
+    }
+
     #[test]
     fn test_set_secrets() {
         let secret_name = "TEST_SECRET";
@@ -183,6 +701,57 @@ mod tests {
         let _ = std::fs::remove_file(&output_path);
     }
 
+    #[test]
+    fn test_issue_file_command_rejects_injected_keys() {
+        let path = std::env::temp_dir().join(format!("github_env_test_{}", Uuid::new_v4()));
+
+        for key in [
+            "",
+            "NAME\nINJECTED<<EOF",
+            "NAME\r",
+            "NAME=value",
+            "NAME<<EOF",
+        ] {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap();
+            assert!(issue_file_command(file, key, "value").is_err(), "{key:?}");
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_heredoc_delimiter_is_not_in_value() {
+        let value = "line one\nghadelimiter_1\nINJECTED=true";
+
+        let mut candidates = ["ghadelimiter_1", "ghadelimiter_2"].into_iter();
+        let delimiter =
+            heredoc_delimiter(value, || candidates.next().unwrap().to_string()).unwrap();
+        assert_eq!(delimiter, "ghadelimiter_2");
+
+        assert!(heredoc_delimiter(value, || "ghadelimiter_1".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_missing_secrets() {
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+        let mappings = HashMap::from([
+            (one, vec![SecretMapping::new("ONE")]),
+            (two, vec![SecretMapping::new("TWO")]),
+        ]);
+
+        assert_eq!(
+            missing_secrets(&mappings, &HashSet::from([one])),
+            vec![(two, &SecretMapping::new("TWO"))]
+        );
+        assert!(missing_secrets(&mappings, &HashSet::from([one, two])).is_empty());
+    }
+
     #[test]
     fn test_parse_secret_lines() {
         let id_to_name_map = parse_secret_input(vec![
@@ -193,13 +762,19 @@ mod tests {
 
         assert_eq!(id_to_name_map.len(), 2);
         assert_eq!(
-            id_to_name_map.get(&Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap()),
-            Some(&"ONE".to_string())
+            names(
+                &id_to_name_map,
+                &SecretRef::Id(Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap())
+            ),
+            vec!["ONE"]
         );
 
         assert_eq!(
-            id_to_name_map.get(&Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap()),
-            Some(&"TWO".to_string())
+            names(
+                &id_to_name_map,
+                &SecretRef::Id(Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap())
+            ),
+            vec!["TWO"]
         );
     }
 
@@ -214,18 +789,101 @@ mod tests {
         assert_eq!(id_to_name_map.len(), 1); // We expect only one entry since the UUID is the same
 
         assert_eq!(
-            id_to_name_map.get(&Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap()),
-            Some(&"TWO".to_string())
+            names(
+                &id_to_name_map,
+                &SecretRef::Id(Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap())
+            ),
+            vec!["ONE", "TWO"]
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines_duplicate_name() {
+        let id_to_name_map = parse_secret_input(vec![
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE".to_string(),
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE?".to_string(),
+        ])
+        .unwrap();
+
+        let mappings = &id_to_name_map
+            [&SecretRef::Id(Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap())];
+        assert_eq!(mappings.len(), 1);
+        assert_eq!(mappings[0].fallback, Fallback::Optional);
+    }
+
+    #[test]
+    fn test_parse_secret_lines_selectors() {
+        let id_to_name_map = parse_secret_input(vec![
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074#.credentials.client_id > CLIENT_ID".to_string(),
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074#.credentials.client_secret > CLIENT_SECRET"
+                .to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            names(
+                &id_to_name_map,
+                &SecretRef::Id(Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap())
+            ),
+            vec!["CLIENT_ID", "CLIENT_SECRET"]
         );
     }
 
     #[test]
-    fn test_parse_secret_lines_invalid_uuid() {
+    fn test_parse_secret_lines_project() {
         let id_to_name_map = parse_secret_input(vec![
-            "invalid-uuid > INVALID".to_string(),
+            "project:bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d > SERVICE_*".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            names(
+                &id_to_name_map,
+                &SecretRef::Project(
+                    Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap()
+                )
+            ),
+            vec!["SERVICE_*"]
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines_empty_reference() {
+        let id_to_name_map = parse_secret_input(vec![
+            " > INVALID".to_string(),
             "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > VALID".to_string(),
         ]);
 
         assert!(id_to_name_map.is_err());
     }
+
+    #[test]
+    fn test_parse_secret_lines_by_key() {
+        let id_to_name_map = parse_secret_input(vec![
+            "prod-db/password > DB_PASSWORD".to_string(),
+            "api-key > API_KEY".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            names(
+                &id_to_name_map,
+                &SecretRef::Key {
+                    project: Some("prod-db".to_string()),
+                    key: "password".to_string()
+                }
+            ),
+            vec!["DB_PASSWORD"]
+        );
+        assert_eq!(
+            names(
+                &id_to_name_map,
+                &SecretRef::Key {
+                    project: None,
+                    key: "api-key".to_string()
+                }
+            ),
+            vec!["API_KEY"]
+        );
+    }
 }
@@ -0,0 +1,226 @@
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+
+use crate::lookup::SecretRef;
+use crate::names;
+use crate::selector::Selector;
+use crate::transform::{self, Transform};
+
+/// What to do when a mapped secret does not exist or cannot be accessed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fallback {
+    /// `REF > NAME`; fail the run
+    Required,
+    /// `REF > NAME?`; leave the variable unset
+    Optional,
+    /// `REF > NAME = default`; set the variable to the default value
+    Default(String),
+}
+
+/// The right-hand side of a `secrets` input line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretMapping {
+    pub name: String,
+    pub fallback: Fallback,
+    /// Extracts a field from a JSON-valued secret instead of using the whole value
+    pub selector: Option<Selector>,
+    /// Applied in order to the value before it is set
+    pub transforms: Vec<Transform>,
+}
+
+impl SecretMapping {
+    /// A required mapping to `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            fallback: Fallback::Required,
+            selector: None,
+            transforms: Vec::new(),
+        }
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.fallback == Fallback::Required
+    }
+}
+
+/// Parses one line of the `secrets` input:
+///
+/// ```text
+/// line      = ref [ "#" selector ] ">" name [ "?" | "=" default ] { "|" transform }
+/// ref       = UUID | key | project "/" key | "project:" UUID
+/// default   = text | '"' text '"'
+/// transform = "base64decode" | "base64encode" | "trim" | "tofile"
+/// ```
+///
+/// See `Selector` for the selector grammar.
+///
+/// Whitespace around each part is ignored. A default may be quoted to keep leading or trailing
+/// spaces, or to contain `|`.
+pub fn parse_line(line: &str) -> Result<(SecretRef, SecretMapping)> {
+    let Some((reference, target)) = line.split_once('>') else {
+        bail!("Expected 'REF > NAME', found: {line}");
+    };
+
+    let (reference, selector) = match reference.split_once('#') {
+        Some((reference, selector)) => (reference, Some(Selector::from_str(selector)?)),
+        None => (reference, None),
+    };
+    let secret_ref = SecretRef::from_str(reference)?;
+    if selector.is_some() && matches!(secret_ref, SecretRef::Project(_)) {
+        bail!("A selector cannot be used with {secret_ref}");
+    }
+
+    let target = target.trim();
+    let name_end = target
+        .find(|c: char| c == '?' || c == '=' || c == '|' || c.is_whitespace())
+        .unwrap_or(target.len());
+    let (name, rest) = target.split_at(name_end);
+    if name.is_empty() {
+        bail!("Missing a name after '>' in: {line}");
+    }
+
+    match (&secret_ref, name.strip_suffix('*')) {
+        // the prefix of the names of every secret in the project, which may be empty
+        (SecretRef::Project(_), Some("")) => {}
+        (SecretRef::Project(_), Some(prefix)) => names::validate(prefix)?,
+        _ => names::validate(name)?,
+    }
+
+    let rest = rest.trim_start();
+    let (fallback, transforms) = if let Some(after) = rest.strip_prefix('?') {
+        (Fallback::Optional, after)
+    } else if let Some(default) = rest.strip_prefix('=') {
+        let (default, after) = split_default(default.trim())?;
+        (Fallback::Default(default.to_string()), after)
+    } else {
+        (Fallback::Required, rest)
+    };
+
+    let transforms = match transform::parse_chain(transforms) {
+        Ok(transforms) => transforms,
+        Err(_) if !transforms.trim_start().starts_with('|') => {
+            bail!(
+                "Unexpected '{}' after '{name}'. Names cannot contain spaces",
+                transforms.trim()
+            );
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok((
+        secret_ref,
+        SecretMapping {
+            name: name.to_string(),
+            fallback,
+            selector,
+            transforms,
+        },
+    ))
+}
+
+/// Splits a default value from the transforms that follow it. An unquoted default ends at the
+/// first `|`.
+fn split_default(value: &str) -> Result<(&str, &str)> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return quoted
+            .split_once('"')
+            .ok_or_else(|| anyhow::anyhow!("Unterminated quoted default: {value}"));
+    }
+
+    match value.split_once('|') {
+        Some((default, _)) => Ok((default.trim_end(), &value[default.len()..])),
+        None => Ok((value, "")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    const ID: &str = "91ba3f10-a9a2-4795-bacf-0eee2d39a074";
+
+    #[test]
+    fn test_parse_line_fallbacks() {
+        let (secret_ref, mapping) = parse_line(&format!("{ID} > NAME")).unwrap();
+        assert_eq!(secret_ref, SecretRef::Id(Uuid::from_str(ID).unwrap()));
+        assert_eq!(mapping, SecretMapping::new("NAME"));
+
+        let (_, mapping) = parse_line(&format!("{ID}>NAME?")).unwrap();
+        assert_eq!(mapping.fallback, Fallback::Optional);
+
+        let (_, mapping) = parse_line(&format!("{ID} > NAME = fallback value")).unwrap();
+        assert_eq!(mapping.name, "NAME");
+        assert_eq!(
+            mapping.fallback,
+            Fallback::Default("fallback value".to_string())
+        );
+
+        let (_, mapping) = parse_line(&format!("{ID} > NAME=\" padded \"")).unwrap();
+        assert_eq!(mapping.fallback, Fallback::Default(" padded ".to_string()));
+
+        let (_, mapping) = parse_line(&format!("{ID} > NAME =")).unwrap();
+        assert_eq!(mapping.fallback, Fallback::Default(String::new()));
+    }
+
+    #[test]
+    fn test_parse_line_selector() {
+        let (secret_ref, mapping) =
+            parse_line(&format!("{ID}#.credentials.client_id > CLIENT_ID")).unwrap();
+        assert_eq!(secret_ref, SecretRef::Id(Uuid::from_str(ID).unwrap()));
+        assert_eq!(mapping.name, "CLIENT_ID");
+        assert_eq!(
+            mapping.selector,
+            Some(Selector::from_str(".credentials.client_id").unwrap())
+        );
+
+        let (secret_ref, mapping) = parse_line("prod/service-account#.key > KEY?").unwrap();
+        assert_eq!(
+            secret_ref,
+            SecretRef::from_str("prod/service-account").unwrap()
+        );
+        assert_eq!(mapping.fallback, Fallback::Optional);
+        assert!(mapping.selector.is_some());
+    }
+
+    #[test]
+    fn test_parse_line_transforms() {
+        let (_, mapping) = parse_line(&format!("{ID} > KEYSTORE | base64decode | tofile")).unwrap();
+        assert_eq!(mapping.name, "KEYSTORE");
+        assert_eq!(
+            mapping.transforms,
+            vec![Transform::Base64Decode, Transform::ToFile]
+        );
+
+        let (_, mapping) = parse_line(&format!("{ID} > NAME? | trim")).unwrap();
+        assert_eq!(mapping.fallback, Fallback::Optional);
+        assert_eq!(mapping.transforms, vec![Transform::Trim]);
+
+        let (_, mapping) = parse_line(&format!("{ID} > NAME = a value | trim")).unwrap();
+        assert_eq!(mapping.fallback, Fallback::Default("a value".to_string()));
+        assert_eq!(mapping.transforms, vec![Transform::Trim]);
+
+        let (_, mapping) = parse_line(&format!("{ID} > NAME = \"a | b\"")).unwrap();
+        assert_eq!(mapping.fallback, Fallback::Default("a | b".to_string()));
+        assert!(mapping.transforms.is_empty());
+    }
+
+    #[test]
+    fn test_parse_line_invalid() {
+        assert!(parse_line(ID).is_err());
+        assert!(parse_line(&format!("{ID} > ")).is_err());
+        assert!(parse_line(&format!("{ID} > TWO WORDS")).is_err());
+        assert!(parse_line(&format!("{ID} > NAME? extra")).is_err());
+        assert!(parse_line(&format!("{ID}#credentials > NAME")).is_err());
+        assert!(parse_line(&format!("project:{ID}#.field > PREFIX_*")).is_err());
+        assert!(parse_line(&format!("{ID} > NAME | unzip")).is_err());
+        assert!(parse_line(&format!("{ID} > my-secret")).is_err());
+        assert!(parse_line(&format!("{ID} > NAME*")).is_err());
+        assert!(parse_line(&format!("project:{ID} > BAD-PREFIX_*")).is_err());
+        assert!(parse_line(&format!("project:{ID} > *")).is_ok());
+        assert!(parse_line(&format!("{ID} > NAME = \"unterminated")).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+
+/// Lines of a multi-line value shorter than this are not masked on their own, since masking
+/// something like `-----` or `}` would hide unrelated log output.
+const MIN_MASKED_LINE_LEN: usize = 8;
+
+/// Derived forms shorter than this are not masked, since they would hide unrelated log output.
+const MIN_ENCODED_LEN: usize = 8;
+
+/// Masks a value in the GitHub Actions logs to prevent it from being displayed.
+pub fn mask_value(value: &str) {
+    for mask in masks(value) {
+        println!("::add-mask::{mask}");
+    }
+}
+
+/// Returns the masks to register for a value. A workflow command ends at the first line break,
+/// so each line of a multi-line value is masked on its own.
+fn masks(value: &str) -> Vec<&str> {
+    if !value.contains(['\n', '\r']) {
+        return vec![value];
+    }
+
+    value
+        .split(['\n', '\r'])
+        .map(str::trim)
+        .filter(|line| line.chars().count() >= MIN_MASKED_LINE_LEN)
+        .collect()
+}
+
+/// Masks the forms of a secret that tools commonly print instead of the raw value: base64
+/// (standard and URL-safe), URL-encoded and JSON-escaped.
+pub fn mask_encodings(value: &str) {
+    for encoded in encodings(value) {
+        mask_value(&encoded);
+    }
+}
+
+/// Returns the derived forms of `value` that differ from it.
+///
+/// A secret encoded as part of a longer base64 string, such as `user:token` in a basic auth
+/// header, only lines up with the secret encoded on its own at one in three offsets. So each
+/// base64 form is computed at all three offsets, keeping only the characters that depend on
+/// nothing but the secret.
+fn encodings(value: &str) -> Vec<String> {
+    let mut encoded = Vec::new();
+
+    for offset in 0..3 {
+        let mut bytes = vec![0; offset];
+        bytes.extend_from_slice(value.as_bytes());
+
+        // characters that mix bits of the prefix or of whatever follows the secret
+        let start = (offset * 8).div_ceil(6);
+        let end = bytes.len() * 8 / 6;
+
+        for engine in [&STANDARD, &URL_SAFE_NO_PAD] {
+            let base64 = engine.encode(&bytes);
+            if let Some(aligned) = base64.get(start..end.min(base64.len())) {
+                encoded.push(aligned.to_string());
+            }
+        }
+    }
+
+    encoded.push(url_encode(value));
+
+    if let Ok(json) = serde_json::to_string(value) {
+        // without the surrounding quotes
+        encoded.push(json[1..json.len() - 1].to_string());
+    }
+
+    let mut unique: Vec<String> = Vec::with_capacity(encoded.len());
+    for form in encoded {
+        if form.len() >= MIN_ENCODED_LEN && form != value && !unique.contains(&form) {
+            unique.push(form);
+        }
+    }
+    unique
+}
+
+/// Percent-encodes everything except the unreserved characters of RFC 3986.
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks() {
+        assert_eq!(masks("single-line"), vec!["single-line"]);
+        assert_eq!(
+            masks(
+                "-----BEGIN KEY-----\r\nMIIEvQIBADANBgkq\n  hkiG9w0BAQEF\nab\n-----\n\n-----END KEY-----\n"
+            ),
+            vec![
+                "-----BEGIN KEY-----",
+                "MIIEvQIBADANBgkq",
+                "hkiG9w0BAQEF",
+                "-----END KEY-----"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encodings() {
+        let secret = "p@ss/word+1234\"";
+        let forms = encodings(secret);
+
+        assert!(forms.contains(&STANDARD.encode(secret)));
+        assert!(forms.contains(&"p%40ss%2Fword%2B1234%22".to_string()));
+        assert!(forms.contains(&"p@ss/word+1234\\\"".to_string()));
+        assert!(!forms.contains(&secret.to_string()));
+
+        // the secret embedded in a longer base64 string is still covered
+        let header = STANDARD.encode(format!("user:{secret}"));
+        assert!(forms.iter().any(|form| header.contains(form.as_str())));
+    }
+
+    #[test]
+    fn test_encodings_skip_short_forms() {
+        assert!(encodings("abc").is_empty());
+        // URL and JSON forms of a plain value are the value itself
+        assert!(
+            encodings("plainvalue")
+                .iter()
+                .all(|form| form != "plainvalue")
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{Result, bail};
+
+/// Variables that change how later steps run: the search path, dynamic linker and interpreter
+/// hooks that inject code into every process.
+const RESERVED_NAMES: &[&str] = &[
+    "BASH_ENV",
+    "DYLD_INSERT_LIBRARIES",
+    "DYLD_LIBRARY_PATH",
+    "ENV",
+    "JAVA_TOOL_OPTIONS",
+    "LD_AUDIT",
+    "LD_LIBRARY_PATH",
+    "LD_PRELOAD",
+    "NODE_OPTIONS",
+    "PATH",
+    "PERL5OPT",
+    "PROMPT_COMMAND",
+    "PYTHONSTARTUP",
+    "RUBYOPT",
+];
+
+/// Prefixes used by the runner for its own configuration.
+const RESERVED_PREFIXES: &[&str] = &["ACTIONS_", "GITHUB_", "RUNNER_"];
+
+/// Checks that `name` follows GitHub's rules for environment variable names: only ASCII
+/// letters, digits and `_`, not starting with a digit.
+pub fn validate(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Name must not be empty");
+    }
+
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '_')
+    {
+        bail!("'{name}' is not a valid name: '{c}' is not allowed. Use letters, digits and '_'");
+    }
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        bail!("'{name}' is not a valid name: it must not start with a digit");
+    }
+
+    Ok(())
+}
+
+/// Fails if any of the names would override a reserved variable, unless it is listed in
+/// `allowed`. Names are compared case-insensitively, as on Windows runners.
+pub fn check_reserved<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    allowed: &[String],
+) -> Result<()> {
+    let mut reserved: Vec<&str> = names
+        .into_iter()
+        .filter(|name| is_reserved(name))
+        .filter(|name| !allowed.iter().any(|a| a.eq_ignore_ascii_case(name)))
+        .collect();
+
+    if reserved.is_empty() {
+        return Ok(());
+    }
+
+    reserved.sort_unstable();
+    bail!(
+        "{} would override reserved environment variables that can change how the rest of the job runs. Add them to allow_reserved_names to set them anyway",
+        reserved.join(", ")
+    )
+}
+
+fn is_reserved(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    RESERVED_NAMES.contains(&name.as_str())
+        || RESERVED_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("DB_PASSWORD").is_ok());
+        assert!(validate("_private2").is_ok());
+
+        assert!(validate("").is_err());
+        assert!(validate("TWO WORDS").is_err());
+        assert!(validate("with-dash").is_err());
+        assert!(validate("2FA").is_err());
+        assert!(validate("NAME\nINJECTED=1").is_err());
+    }
+
+    #[test]
+    fn test_check_reserved() {
+        assert!(check_reserved(["DB_PASSWORD", "GITHUB"], &[]).is_ok());
+
+        let error = check_reserved(["PATH", "github_token", "API_KEY"], &[]).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("PATH, github_token would override")
+        );
+
+        assert!(check_reserved(["RUNNER_TEMP"], &[]).is_err());
+        assert!(check_reserved(["LD_PRELOAD"], &["ld_preload".to_string()]).is_ok());
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Result;
+use reqwest::Url;
+
+use crate::config::get_env;
+use crate::debug;
+use crate::mask::mask_value;
+use crate::retry::{HttpStatusError, retry};
+use crate::timeout::Timeouts;
+
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+
+/// Exchanges the job's GitHub OIDC token for a short-lived machine account access token.
+///
+/// The job needs the `id-token: write` permission for the runner to provide
+/// `ACTIONS_ID_TOKEN_REQUEST_URL` and `ACTIONS_ID_TOKEN_REQUEST_TOKEN`.
+pub async fn access_token(
+    http: &reqwest::Client,
+    exchange_url: &str,
+    audience: Option<&str>,
+    max_retries: u32,
+    timeouts: Timeouts,
+) -> Result<String> {
+    let request_url = get_env("ACTIONS_ID_TOKEN_REQUEST_URL").ok_or_else(|| {
+        anyhow::anyhow!(
+            "ACTIONS_ID_TOKEN_REQUEST_URL is not set. Ensure the job has the 'id-token: write' permission."
+        )
+    })?;
+    let request_token = get_env("ACTIONS_ID_TOKEN_REQUEST_TOKEN").ok_or_else(|| {
+        anyhow::anyhow!(
+            "ACTIONS_ID_TOKEN_REQUEST_TOKEN is not set. Ensure the job has the 'id-token: write' permission."
+        )
+    })?;
+
+    let id_token = retry(max_retries, timeouts, "GitHub OIDC token request", || {
+        request_id_token(http, &request_url, &request_token, audience)
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!("Failed to request a GitHub OIDC token.\nError: {e}"))?;
+
+    retry(max_retries, timeouts, "GitHub OIDC token exchange", || {
+        exchange(http, exchange_url, &id_token)
+    })
+    .await
+    .map_err(|e| {
+        anyhow::anyhow!("Failed to exchange the GitHub OIDC token at {exchange_url}.\nError: {e}")
+    })
+}
+
+/// Requests an OIDC token for the job from the runner.
+async fn request_id_token(
+    http: &reqwest::Client,
+    request_url: &str,
+    request_token: &str,
+    audience: Option<&str>,
+) -> Result<String> {
+    let mut url = Url::parse(request_url)
+        .map_err(|e| anyhow::anyhow!("Invalid ACTIONS_ID_TOKEN_REQUEST_URL.\nError: {e}"))?;
+    if let Some(audience) = audience {
+        url.query_pairs_mut().append_pair("audience", audience);
+    }
+
+    debug!("Requesting GitHub OIDC token");
+    let response = http.get(url).bearer_auth(request_token).send().await?;
+    let response: serde_json::Value = HttpStatusError::check(response)?.json().await?;
+
+    let id_token = response["value"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("The GitHub OIDC token response did not contain a token"))?;
+    mask_value(id_token);
+
+    Ok(id_token.to_string())
+}
+
+/// Exchanges the OIDC token at the identity endpoint, following RFC 8693. The endpoint responds
+/// with a machine account access token in `access_token`.
+async fn exchange(http: &reqwest::Client, exchange_url: &str, id_token: &str) -> Result<String> {
+    debug!("Exchanging GitHub OIDC token at {exchange_url}");
+    let response = http
+        .post(exchange_url)
+        .form(&[
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("subject_token", id_token),
+            ("subject_token_type", JWT_TOKEN_TYPE),
+        ])
+        .send()
+        .await?;
+    let response: serde_json::Value = HttpStatusError::check(response)?.json().await?;
+
+    let access_token = response["access_token"].as_str().ok_or_else(|| {
+        anyhow::anyhow!(
+            "The token exchange response from {exchange_url} did not contain an access_token"
+        )
+    })?;
+    mask_value(access_token);
+
+    Ok(access_token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    /// Reads one HTTP request, returning the lowercased head and the body.
+    async fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+
+        let header_end = loop {
+            let n = stream.read(&mut buf).await.ok().filter(|n| *n > 0)?;
+            request.extend_from_slice(&buf[..n]);
+            if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&request[..header_end]).to_lowercase();
+        let content_length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .map_or(0, |len| len.trim().parse().unwrap_or(0));
+
+        while request.len() < header_end + content_length {
+            let n = stream.read(&mut buf).await.ok().filter(|n| *n > 0)?;
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let body = String::from_utf8_lossy(&request[header_end..]).to_string();
+        Some((head, body))
+    }
+
+    /// Starts a local stand-in for the runner token endpoint and the identity server.
+    async fn mock_identity_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let Some((head, body)) = read_request(&mut stream).await else {
+                    continue;
+                };
+
+                let (status, response) = if head.starts_with("get /token?")
+                    && head.contains("audience=sm-action")
+                    && head.contains("authorization: bearer runner-token")
+                {
+                    ("200 OK", r#"{"value":"github-jwt"}"#)
+                } else if head.starts_with("post /exchange")
+                    && body.contains("subject_token=github-jwt")
+                {
+                    ("200 OK", r#"{"access_token":"0.short-lived.token:key"}"#)
+                } else {
+                    ("401 Unauthorized", "{}")
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn test_oidc_exchange() {
+        let server = mock_identity_server().await;
+        let http = reqwest::Client::new();
+
+        let id_token = request_id_token(
+            &http,
+            &format!("{server}/token?api-version=2.0"),
+            "runner-token",
+            Some("sm-action"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(id_token, "github-jwt");
+
+        let access_token = exchange(&http, &format!("{server}/exchange"), &id_token)
+            .await
+            .unwrap();
+        assert_eq!(access_token, "0.short-lived.token:key");
+    }
+
+    #[tokio::test]
+    async fn test_oidc_exchange_rejected() {
+        let server = mock_identity_server().await;
+        let http = reqwest::Client::new();
+
+        assert!(
+            request_id_token(
+                &http,
+                &format!("{server}/token?api-version=2.0"),
+                "wrong-token",
+                Some("sm-action"),
+            )
+            .await
+            .is_err()
+        );
+        assert!(
+            exchange(&http, &format!("{server}/exchange"), "forged-jwt")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+
+use crate::debug;
+
+/// File formats supported by the `output_file` input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Dotenv,
+    Json,
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "dotenv" | "env" => Ok(Self::Dotenv),
+            "json" => Ok(Self::Json),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => bail!("Output format must be one of 'dotenv', 'json' or 'yaml'"),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Infers the format from the file extension, falling back to dotenv.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => Self::from_str(ext).unwrap_or_default(),
+            None => Self::default(),
+        }
+    }
+}
+
+/// Writes the secrets to `path` in the given format. The file is only readable by the current
+/// user and replaces any existing file atomically, so readers never see a partial file.
+pub fn write_secrets_file(
+    path: &str,
+    format: OutputFormat,
+    secrets: &BTreeMap<&str, &str>,
+) -> Result<()> {
+    debug!("Writing {} secrets to {path} as {format:?}", secrets.len());
+    let contents = render(format, secrets)?;
+    write_atomic(Path::new(path), contents.as_bytes(), 0o600)
+}
+
+/// Writes each secret to its own file, `<dir>/<NAME>`, readable only by the current user. This
+/// mirrors how Docker and Kubernetes mount secrets, and keeps multi-line values such as PEM keys
+/// intact.
+pub fn write_secret_files(dir: &str, secrets: &BTreeMap<&str, &str>) -> Result<()> {
+    let dir = Path::new(dir);
+    debug!("Writing {} secrets to {}", secrets.len(), dir.display());
+
+    if !dir.exists() {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder
+            .create(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {}.\nError: {e}", dir.display()))?;
+    }
+
+    for (name, value) in secrets {
+        if name.is_empty() || *name == "." || *name == ".." || name.contains(['/', '\\']) {
+            bail!("'{name}' cannot be used as a file name");
+        }
+        write_atomic(&dir.join(name), value.as_bytes(), 0o400)?;
+    }
+
+    Ok(())
+}
+
+fn render(format: OutputFormat, secrets: &BTreeMap<&str, &str>) -> Result<String> {
+    let mut contents = String::new();
+
+    match format {
+        OutputFormat::Dotenv => {
+            for (name, value) in secrets {
+                contents.push_str(&format!("{name}=\"{}\"\n", escape_dotenv(value)));
+            }
+        }
+        OutputFormat::Json => {
+            contents = serde_json::to_string_pretty(secrets)?;
+            contents.push('\n');
+        }
+        OutputFormat::Yaml => {
+            // a JSON string is a valid YAML double-quoted scalar, escapes included
+            for (name, value) in secrets {
+                contents.push_str(&format!(
+                    "{}: {}\n",
+                    serde_json::to_string(name)?,
+                    serde_json::to_string(value)?
+                ));
+            }
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Escapes a value for a double-quoted dotenv value, as understood by docker compose and
+/// most dotenv libraries.
+fn escape_dotenv(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '$' => escaped.push_str("\\$"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes `contents` to a temporary file next to `path` with the given permissions and renames
+/// it over `path`.
+pub fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid output path: {}", path.display()))?;
+    let temp_path = path.with_file_name(format!(".{file_name}.{}.tmp", uuid::Uuid::new_v4()));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let result = options.open(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    });
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        bail!("Failed to write {}.\nError: {e}", path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets() -> BTreeMap<&'static str, &'static str> {
+        BTreeMap::from([
+            ("PEM", "-----BEGIN KEY-----\nabc\n-----END KEY-----"),
+            ("QUOTED", r#"say "hi" to $USER\now"#),
+        ])
+    }
+
+    #[test]
+    fn test_output_format_from_path() {
+        assert_eq!(OutputFormat::from_path("secrets.json"), OutputFormat::Json);
+        assert_eq!(OutputFormat::from_path("values.yml"), OutputFormat::Yaml);
+        assert_eq!(OutputFormat::from_path(".env"), OutputFormat::Dotenv);
+        assert_eq!(OutputFormat::from_path("secrets"), OutputFormat::Dotenv);
+    }
+
+    #[test]
+    fn test_render_escapes_values() {
+        assert_eq!(
+            render(OutputFormat::Dotenv, &secrets()).unwrap(),
+            "PEM=\"-----BEGIN KEY-----\\nabc\\n-----END KEY-----\"\n\
+             QUOTED=\"say \\\"hi\\\" to \\$USER\\\\now\"\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Yaml, &secrets()).unwrap(),
+            "\"PEM\": \"-----BEGIN KEY-----\\nabc\\n-----END KEY-----\"\n\
+             \"QUOTED\": \"say \\\"hi\\\" to $USER\\\\now\"\n"
+        );
+
+        let json: BTreeMap<String, String> =
+            serde_json::from_str(&render(OutputFormat::Json, &secrets()).unwrap()).unwrap();
+        assert_eq!(json["QUOTED"], secrets()["QUOTED"]);
+    }
+
+    #[test]
+    fn test_write_secrets_file() {
+        let path =
+            std::env::temp_dir().join(format!("sm_action_output_{}.env", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, "stale").unwrap();
+        write_secrets_file(path, OutputFormat::Dotenv, &secrets()).unwrap();
+
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert!(contents.starts_with("PEM=\""));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_write_secret_files() {
+        let dir = std::env::temp_dir().join(format!("sm_action_files_{}", uuid::Uuid::new_v4()));
+        let dir = dir.to_str().unwrap();
+
+        write_secret_files(dir, &secrets()).unwrap();
+        // existing read-only files are replaced on the next run
+        write_secret_files(dir, &secrets()).unwrap();
+
+        let pem_path = Path::new(dir).join("PEM");
+        assert_eq!(
+            std::fs::read_to_string(&pem_path).unwrap(),
+            secrets()["PEM"]
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&pem_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o400);
+        }
+
+        assert!(write_secret_files(dir, &BTreeMap::from([("../PEM", "value")])).is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::{Map, Value, json};
+use uuid::Uuid;
+
+use crate::debug;
+use crate::error;
+use crate::output::write_atomic;
+
+/// A machine-readable record of a run, written to `report_file` for audit tooling. It describes
+/// what was requested and where it went, but never contains secret values.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub path: Option<String>,
+    pub servers: Vec<(String, String, String)>, // account name, API URL and identity URL
+    timings: Vec<(String, Duration)>,
+    pub requested_ids: Vec<Uuid>,
+    pub returned_ids: Vec<Uuid>,
+    pub names: Vec<String>,
+    pub sinks: Vec<String>,
+}
+
+impl RunReport {
+    /// Records how long a phase of the run took.
+    pub fn time(&mut self, phase: &str, elapsed: Duration) {
+        debug!("{phase} took {elapsed:?}");
+        self.timings.push((phase.to_string(), elapsed));
+    }
+
+    /// Writes the report if `report_file` was set. A run that failed, or whose command exited
+    /// with a non-zero code in exec mode, is reported as a failure.
+    pub fn write(&self, result: &Result<i32>) -> Result<()> {
+        let Some(path) = self.path.as_deref() else {
+            return Ok(());
+        };
+
+        debug!("Writing run report to {path}");
+        let contents = serde_json::to_string_pretty(&self.to_json(result))?;
+        write_atomic(Path::new(path), contents.as_bytes(), 0o600)
+    }
+
+    fn to_json(&self, result: &Result<i32>) -> Value {
+        let sorted = |ids: &[Uuid]| {
+            let mut ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+            ids.sort();
+            ids
+        };
+
+        let accounts: Vec<Value> = self
+            .servers
+            .iter()
+            .map(|(name, api_url, identity_url)| {
+                json!({ "name": name, "api_url": api_url, "identity_url": identity_url })
+            })
+            .collect();
+
+        let timings: Map<String, Value> = self
+            .timings
+            .iter()
+            .map(|(phase, elapsed)| (format!("{phase}_ms"), json!(elapsed.as_millis() as u64)))
+            .collect();
+
+        let error = match result {
+            Ok(0) => None,
+            Ok(code) => Some(format!("The command exited with code {code}")),
+            Err(e) => Some(format!("{e:#}")),
+        };
+
+        json!({
+            "status": if error.is_some() { "failure" } else { "success" },
+            "error": error,
+            "accounts": accounts,
+            "timings": timings,
+            "requested_ids": sorted(&self.requested_ids),
+            "returned_ids": sorted(&self.returned_ids),
+            "names": self.names,
+            "sinks": self.sinks,
+            "warnings": error::warnings(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let id = Uuid::new_v4();
+        let mut report = RunReport {
+            servers: vec![(
+                "default".to_string(),
+                "https://api.bitwarden.com".to_string(),
+                "https://identity.bitwarden.com".to_string(),
+            )],
+            requested_ids: vec![id],
+            returned_ids: vec![id],
+            names: vec!["DB_PASSWORD".to_string()],
+            sinks: vec!["env".to_string(), "output".to_string()],
+            ..Default::default()
+        };
+        report.time("login", Duration::from_millis(120));
+        report.time("infra.login", Duration::from_millis(80));
+
+        let json = report.to_json(&Ok(0));
+        assert_eq!(json["status"], "success");
+        assert_eq!(json["error"], Value::Null);
+        assert_eq!(json["accounts"][0]["name"], "default");
+        assert_eq!(json["accounts"][0]["api_url"], "https://api.bitwarden.com");
+        assert_eq!(json["timings"]["login_ms"], 120);
+        assert_eq!(json["timings"]["infra.login_ms"], 80);
+        assert_eq!(json["requested_ids"][0], id.to_string());
+        assert_eq!(json["names"][0], "DB_PASSWORD");
+
+        let json = report.to_json(&Err(anyhow::anyhow!("Secrets are required")));
+        assert_eq!(json["status"], "failure");
+        assert_eq!(json["error"], "Secrets are required");
+
+        let json = report.to_json(&Ok(3));
+        assert_eq!(json["status"], "failure");
+        assert_eq!(json["error"], "The command exited with code 3");
+    }
+
+    #[test]
+    fn test_write() {
+        let path = std::env::temp_dir().join(format!("sm_action_report_{}.json", Uuid::new_v4()));
+        let report = RunReport {
+            path: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+
+        report.write(&Ok(0)).unwrap();
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["status"], "success");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,269 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::StatusCode;
+
+use crate::debug;
+use crate::error::ActionError;
+use crate::timeout::Timeouts;
+
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The Bitwarden SDK only exposes the status of a failed request through its error message, and
+/// none of its headers, so `Retry-After` is only known for requests the action makes itself.
+const TRANSIENT_MESSAGES: &[&str] = &[
+    "429 Too Many Requests",
+    "500 Internal Server Error",
+    "502 Bad Gateway",
+    "503 Service Unavailable",
+    "504 Gateway Timeout",
+    "error sending request",
+    "operation timed out",
+];
+
+/// An error response to a request made by the action itself. Unlike
+/// `reqwest::Response::error_for_status`, this keeps the `Retry-After` header.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub url: String,
+    pub status: StatusCode,
+    pub retry_after: Option<Duration>,
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} responded with {}", self.url, self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+impl HttpStatusError {
+    /// Returns the response if its status is a success, or an `HttpStatusError` otherwise.
+    pub fn check(response: reqwest::Response) -> Result<reqwest::Response, Self> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+
+        Err(Self {
+            url: response.url().to_string(),
+            status,
+            retry_after,
+        })
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails with an error that is not worth retrying, or has been
+/// retried `max_retries` times. Retries wait with exponential backoff and jitter, or for as long
+/// as the server asked with `Retry-After` (up to 30 seconds) where the header is available, but
+/// never past the overall deadline of `timeouts`.
+pub async fn retry<T, F, Fut>(
+    max_retries: u32,
+    timeouts: Timeouts,
+    phase: &str,
+    mut attempt: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut retries = 0;
+
+    loop {
+        debug!("{phase}: attempt {} of {}", retries + 1, max_retries + 1);
+
+        let error = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+
+        let Some(retry_after) = transient(&error) else {
+            debug!(
+                "{phase}: attempt {} failed and will not be retried",
+                retries + 1
+            );
+            return Err(error);
+        };
+
+        if retries >= max_retries {
+            debug!("{phase}: giving up after {} attempts", retries + 1);
+            return Err(error);
+        }
+
+        let mut delay = retry_after.map_or_else(|| backoff(retries), |delay| delay.min(MAX_DELAY));
+        // the next attempt then fails with the overall timeout
+        if let Some(remaining) = timeouts.remaining() {
+            delay = delay.min(remaining);
+        }
+        debug!(
+            "{phase}: attempt {} failed with a transient error; retrying in {delay:?}",
+            retries + 1
+        );
+        tokio::time::sleep(delay).await;
+        retries += 1;
+    }
+}
+
+/// Exponential backoff with "equal jitter": at least half of the capped delay, plus a random
+/// share of the other half so that concurrent jobs do not retry in lockstep.
+fn backoff(retries: u32) -> Duration {
+    let cap = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(retries))
+        .min(MAX_DELAY);
+    let half = cap / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+/// Returns `Some` if the error is worth retrying, with the delay the server asked for, if any.
+fn transient(error: &anyhow::Error) -> Option<Option<Duration>> {
+    // a request that timed out is retried, but not once the overall timeout has run out
+    if let Some(ActionError::Timeout { overall, .. }) = error.downcast_ref::<ActionError>() {
+        return (!overall).then_some(None);
+    }
+
+    if let Some(e) = error.downcast_ref::<HttpStatusError>() {
+        return is_transient_status(e.status).then_some(e.retry_after);
+    }
+
+    if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+        let transient =
+            e.is_timeout() || e.is_connect() || e.status().is_some_and(is_transient_status);
+        return transient.then_some(None);
+    }
+
+    let message = error.to_string();
+    TRANSIENT_MESSAGES
+        .iter()
+        .any(|transient| message.contains(transient))
+        .then_some(None)
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || matches!(status.as_u16(), 500 | 502 | 503 | 504)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_is_capped_and_jittered() {
+        for retries in 0..10 {
+            let cap = BASE_DELAY
+                .saturating_mul(2u32.saturating_pow(retries))
+                .min(MAX_DELAY);
+            let delay = backoff(retries);
+            assert!(
+                delay >= cap / 2 && delay <= cap,
+                "{delay:?} not in [{cap:?}/2, {cap:?}]"
+            );
+        }
+    }
+
+    #[test]
+    fn test_transient() {
+        let rate_limited = anyhow::Error::new(HttpStatusError {
+            url: "https://identity.example.com".to_string(),
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(Duration::from_secs(2)),
+        });
+        assert_eq!(transient(&rate_limited), Some(Some(Duration::from_secs(2))));
+
+        let unauthorized = anyhow::Error::new(HttpStatusError {
+            url: "https://identity.example.com".to_string(),
+            status: StatusCode::UNAUTHORIZED,
+            retry_after: None,
+        });
+        assert_eq!(transient(&unauthorized), None);
+
+        assert_eq!(
+            transient(&anyhow::anyhow!(
+                "Received error message from server: [503 Service Unavailable] "
+            )),
+            Some(None)
+        );
+        assert_eq!(
+            transient(&anyhow::anyhow!("Access token is not in a valid format")),
+            None
+        );
+
+        let timeout = |overall| {
+            anyhow::Error::new(ActionError::Timeout {
+                phase: "Authentication".to_string(),
+                url: "https://identity.example.com".to_string(),
+                limit: Duration::from_secs(60),
+                overall,
+            })
+        };
+        assert_eq!(transient(&timeout(false)), Some(None));
+        assert_eq!(transient(&timeout(true)), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry() {
+        let timeouts = Timeouts::start(None, None);
+
+        let attempts = AtomicU32::new(0);
+        let result = retry(3, timeouts, "test", || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(anyhow::anyhow!("502 Bad Gateway")),
+                _ => Ok("done"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = retry(3, timeouts, "test", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::anyhow!("401 Unauthorized"))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = retry(2, timeouts, "test", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::anyhow!("503 Service Unavailable"))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_stops_waiting_at_the_overall_deadline() {
+        let timeouts = Timeouts::start(None, Some(Duration::from_secs(1)));
+        let start = tokio::time::Instant::now();
+
+        let result: Result<()> = retry(3, timeouts, "test", || {
+            timeouts.request("test", "https://api.example.com", async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Err(anyhow::anyhow!("429 Too Many Requests"))
+            })
+        })
+        .await;
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<ActionError>(),
+            Some(ActionError::Timeout { overall: true, .. })
+        ));
+        assert!(start.elapsed() <= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,147 @@
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+/// A path into a JSON-valued secret, written after `#` in a secret reference:
+/// `UUID#.credentials.client_id > CLIENT_ID`.
+///
+/// ```text
+/// selector = segment { segment }
+/// segment  = "." field | "." '"' text '"' | "[" index "]"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Selector {
+    path: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let path = s.trim();
+        let mut segments = Vec::new();
+        let mut rest = path;
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                if let Some(quoted) = after.strip_prefix('"') {
+                    let Some((field, after)) = quoted.split_once('"') else {
+                        bail!("Unterminated quoted field in selector: {path}");
+                    };
+                    segments.push(Segment::Field(field.to_string()));
+                    rest = after;
+                } else {
+                    let end = after.find(['.', '[']).unwrap_or(after.len());
+                    let field = &after[..end];
+                    if field.is_empty() {
+                        bail!("Empty field name in selector: {path}");
+                    }
+                    segments.push(Segment::Field(field.to_string()));
+                    rest = &after[end..];
+                }
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some((index, after)) = after.split_once(']') else {
+                    bail!("Unterminated index in selector: {path}");
+                };
+                let index = index
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid index '{index}' in selector: {path}"))?;
+                segments.push(Segment::Index(index));
+                rest = after;
+            } else {
+                bail!("Selector must start with '.' or '[', like '.credentials.client_id': {path}");
+            }
+        }
+
+        if segments.is_empty() {
+            bail!("Selector must not be empty");
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            segments,
+        })
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl Selector {
+    /// Extracts the selected value from a JSON document. Strings are returned as they are, like
+    /// `jq -r`; any other value is returned as compact JSON.
+    ///
+    /// Errors never include the document, since it is a secret.
+    pub fn select(&self, json: &str) -> Result<String> {
+        let document: Value = serde_json::from_str(json)
+            .map_err(|e| anyhow::anyhow!("The secret is not valid JSON.\nError: {e}"))?;
+
+        let mut value = &document;
+        for segment in &self.segments {
+            let next = match segment {
+                Segment::Field(field) => value.get(field.as_str()),
+                Segment::Index(index) => value.get(*index),
+            };
+            value = next.ok_or_else(|| match segment {
+                Segment::Field(field) => anyhow::anyhow!("No field '{field}' at {}", self.path),
+                Segment::Index(index) => anyhow::anyhow!("No element [{index}] at {}", self.path),
+            })?;
+        }
+
+        Ok(match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"{
+        "credentials": { "client_id": "abc", "port": 5432, "dotted.key": "x" },
+        "scopes": ["read", "write"]
+    }"#;
+
+    #[test]
+    fn test_selector_select() {
+        let select = |path: &str| Selector::from_str(path).unwrap().select(DOCUMENT);
+
+        assert_eq!(select(".credentials.client_id").unwrap(), "abc");
+        assert_eq!(select(".credentials.port").unwrap(), "5432");
+        assert_eq!(select(".credentials.\"dotted.key\"").unwrap(), "x");
+        assert_eq!(select(".scopes[1]").unwrap(), "write");
+        assert_eq!(select(".scopes").unwrap(), r#"["read","write"]"#);
+
+        assert!(select(".credentials.secret").is_err());
+        assert!(select(".scopes[2]").is_err());
+        assert!(
+            Selector::from_str(".a")
+                .unwrap()
+                .select("not json")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_selector_from_str_invalid() {
+        assert!(Selector::from_str("").is_err());
+        assert!(Selector::from_str("credentials").is_err());
+        assert!(Selector::from_str(".credentials.").is_err());
+        assert!(Selector::from_str(".scopes[x]").is_err());
+        assert!(Selector::from_str(".\"unterminated").is_err());
+    }
+}
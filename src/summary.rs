@@ -0,0 +1,80 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use crate::config::get_env;
+use crate::debug;
+
+/// Where a name in the summary got its value from. Values themselves are never included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Secret { id: Uuid, project_id: Option<Uuid> },
+    Default,
+}
+
+/// Appends a table of the names that were set to the job summary, if the runner provides
+/// `GITHUB_STEP_SUMMARY`.
+pub fn write_step_summary(rows: &[(&str, Source)], set_env: bool) -> Result<()> {
+    let Some(path) = get_env("GITHUB_STEP_SUMMARY") else {
+        return Ok(());
+    };
+
+    debug!("Writing job summary to {path}");
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(render(rows, set_env).as_bytes())?;
+    Ok(())
+}
+
+fn render(rows: &[(&str, Source)], set_env: bool) -> String {
+    let set_as = if set_env { "env and output" } else { "output" };
+
+    let mut summary = String::from("### Bitwarden secrets\n\n");
+    summary.push_str("| Name | Secret | Project | Set as |\n");
+    summary.push_str("| ---- | ------ | ------- | ------ |\n");
+
+    for (name, source) in rows {
+        let (secret, project) = match source {
+            Source::Secret { id, project_id } => (
+                format!("`{id}`"),
+                project_id.map_or_else(String::new, |id| format!("`{id}`")),
+            ),
+            Source::Default => ("default value".to_string(), String::new()),
+        };
+        summary.push_str(&format!("| `{name}` | {secret} | {project} | {set_as} |\n"));
+    }
+
+    summary.push('\n');
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let id = Uuid::new_v4();
+        let project_id = Uuid::new_v4();
+        let summary = render(
+            &[
+                (
+                    "DB_PASSWORD",
+                    Source::Secret {
+                        id,
+                        project_id: Some(project_id),
+                    },
+                ),
+                ("LOG_LEVEL", Source::Default),
+            ],
+            true,
+        );
+
+        assert!(summary.contains(&format!(
+            "| `DB_PASSWORD` | `{id}` | `{project_id}` | env and output |\n"
+        )));
+        assert!(summary.contains("| `LOG_LEVEL` | default value |  | env and output |\n"));
+        assert!(render(&[("NAME", Source::Default)], false).contains("| output |"));
+    }
+}
@@ -0,0 +1,160 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::time::Instant;
+
+use crate::error::ActionError;
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The time limits for requests to the Bitwarden servers: one for each request, and an overall
+/// deadline for all of them, retries included.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    request: Option<Duration>,
+    overall: Option<(Duration, Instant)>, // the limit, and when it runs out
+}
+
+impl Timeouts {
+    /// Starts the overall deadline now.
+    pub fn start(request: Option<Duration>, overall: Option<Duration>) -> Self {
+        Self {
+            request,
+            overall: overall.map(|limit| (limit, Instant::now() + limit)),
+        }
+    }
+
+    /// Returns the time left before the overall deadline, if there is one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.overall
+            .map(|(_, at)| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs a single request, failing if it takes longer than the request timeout or runs past
+    /// the overall deadline.
+    pub async fn request<T>(
+        &self,
+        phase: &str,
+        url: &str,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let request_deadline = self.request.map(|limit| (limit, Instant::now() + limit));
+        self.run(phase, url, request_deadline, request).await
+    }
+
+    /// Runs several requests that time out on their own, such as a request and its retries,
+    /// failing if they run past the overall deadline.
+    pub async fn requests<T>(
+        &self,
+        phase: &str,
+        url: &str,
+        requests: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        self.run(phase, url, None, requests).await
+    }
+
+    async fn run<T>(
+        &self,
+        phase: &str,
+        url: &str,
+        request_deadline: Option<(Duration, Instant)>,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        // whichever runs out first
+        let deadline = match (request_deadline, self.overall) {
+            (Some(request), Some(overall)) if overall.1 < request.1 => Some((overall, true)),
+            (Some(request), _) => Some((request, false)),
+            (None, Some(overall)) => Some((overall, true)),
+            (None, None) => None,
+        };
+
+        let Some(((limit, at), overall)) = deadline else {
+            return future.await;
+        };
+
+        tokio::time::timeout_at(at, future)
+            .await
+            .unwrap_or_else(|_| {
+                Err(ActionError::Timeout {
+                    phase: phase.to_string(),
+                    url: url.to_string(),
+                    limit,
+                    overall,
+                }
+                .into())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn respond_after(delay: Duration) -> Result<&'static str> {
+        tokio::time::sleep(delay).await;
+        Ok("response")
+    }
+
+    fn timeout_error(result: Result<&str>) -> (String, bool) {
+        match result.unwrap_err().downcast_ref::<ActionError>() {
+            Some(ActionError::Timeout { url, overall, .. }) => (url.clone(), *overall),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let timeouts = Timeouts::start(Some(Duration::from_secs(5)), None);
+
+        let response = timeouts
+            .request(
+                "Authentication",
+                "https://identity.example.com",
+                respond_after(Duration::from_secs(1)),
+            )
+            .await;
+        assert_eq!(response.unwrap(), "response");
+
+        let result = timeouts
+            .request(
+                "Authentication",
+                "https://identity.example.com",
+                respond_after(Duration::from_secs(60)),
+            )
+            .await;
+        assert_eq!(
+            timeout_error(result),
+            ("https://identity.example.com".to_string(), false)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overall_timeout() {
+        let timeouts = Timeouts::start(Some(Duration::from_secs(5)), Some(Duration::from_secs(8)));
+
+        for _ in 0..2 {
+            let response = timeouts
+                .request(
+                    "Fetching secrets",
+                    "https://api.example.com",
+                    respond_after(Duration::from_secs(3)),
+                )
+                .await;
+            assert!(response.is_ok());
+        }
+
+        // 6 seconds in, the overall deadline runs out before the request timeout
+        let result = timeouts
+            .request(
+                "Fetching secrets",
+                "https://api.example.com",
+                respond_after(Duration::from_secs(3)),
+            )
+            .await;
+        assert_eq!(
+            timeout_error(result),
+            ("https://api.example.com".to_string(), true)
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rustls::ClientConfig;
+use rustls_platform_verifier::ConfigVerifierExt;
+
+use crate::config::Config;
+use crate::transform::write_temp_file;
+use crate::{debug, error};
+
+/// Whether the platform verifier loads its roots from `SSL_CERT_FILE`. On macOS and Windows it
+/// asks the operating system to verify servers instead.
+pub const SSL_CERT_FILE_SUPPORTED: bool = !cfg!(any(target_os = "macos", target_os = "windows"));
+
+/// Makes the Bitwarden SDK and `http_client` trust the CA certificates in `ca_bundle`, in
+/// addition to the system's.
+///
+/// Both verify servers with the platform verifier, which loads its roots from `SSL_CERT_FILE` on
+/// Linux. That file replaces the system roots, so the one passed holds the system roots followed
+/// by the bundle. It must be set before the async runtime is started.
+pub fn configure(config: &Config) -> Result<()> {
+    let Some(path) = config.ca_bundle.as_deref() else {
+        return Ok(());
+    };
+    let bundle = load_ca_bundle(path)?;
+
+    if !SSL_CERT_FILE_SUPPORTED {
+        error::warning(&format!(
+            "ca_bundle is ignored on {}, where the operating system verifies servers. Add the CA certificates to the system trust store instead",
+            std::env::consts::OS
+        ));
+        return Ok(());
+    }
+
+    let mut roots = system_roots();
+    roots.push_str(&bundle);
+    let roots_file = write_temp_file(roots.as_bytes())?;
+
+    debug!("Trusting the system CA certificates and those in {path}");
+    // SAFETY: this runs before the async runtime starts its threads, while the process is still
+    // single-threaded
+    unsafe { std::env::set_var("SSL_CERT_FILE", &roots_file) };
+
+    Ok(())
+}
+
+/// Builds the HTTP client for requests the action makes itself, such as the OIDC token
+/// exchange, with the request timeout applied. It verifies servers the same way as the SDK's
+/// client, so both trust the same certificates.
+pub fn http_client(config: &Config) -> Result<reqwest::Client> {
+    let tls = ClientConfig::with_platform_verifier().map_err(|e| {
+        anyhow::anyhow!("Failed to set up TLS certificate verification.\nError: {e}")
+    })?;
+    let mut builder = reqwest::Client::builder()
+        .user_agent("bitwarden/sm-action")
+        .use_preconfigured_tls(tls);
+
+    if let Some(timeout) = config.request_timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Reads `ca_bundle`, checking that it holds at least one PEM certificate.
+fn load_ca_bundle(path: &str) -> Result<String> {
+    let pem = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read ca_bundle {path}.\nError: {e}"))?;
+    let certificates = reqwest::Certificate::from_pem_bundle(pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("ca_bundle {path} is not a PEM bundle.\nError: {e}"))?;
+
+    if certificates.is_empty() {
+        bail!("ca_bundle {path} does not contain any certificates");
+    }
+
+    Ok(pem)
+}
+
+/// Returns the system CA certificates as PEM, loaded the same way the platform verifier loads
+/// them on Linux.
+fn system_roots() -> String {
+    let roots = rustls_native_certs::load_native_certs();
+    for e in &roots.errors {
+        debug!("Failed to load some of the system CA certificates: {e}");
+    }
+
+    roots.certs.iter().map(|cert| pem(cert.as_ref())).collect()
+}
+
+fn pem(der: &[u8]) -> String {
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in STANDARD.encode(der).as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bitwarden_core::auth::login::AccessTokenLoginRequest;
+    use bitwarden_core::{Client, ClientSettings, DeviceType};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::error::ActionError;
+
+    fn testdata(name: &str) -> String {
+        format!("{}/testdata/tls/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    fn read_testdata(name: &str) -> Vec<u8> {
+        std::fs::read(testdata(name)).unwrap()
+    }
+
+    /// Starts a local HTTPS server with a certificate from the test CA.
+    async fn tls_stub_server() -> String {
+        let certificates: Vec<CertificateDer> =
+            CertificateDer::pem_slice_iter(&read_testdata("server.pem"))
+                .collect::<Result<_, _>>()
+                .unwrap();
+        let key = PrivateKeyDer::from_pem_slice(&read_testdata("server-key.pem")).unwrap();
+
+        let tls_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                // clients that don't trust the CA fail the handshake
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    )
+                    .await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        format!("https://{address}")
+    }
+
+    /// Logs in with the SDK, the way the action does, and returns whether the server could not be
+    /// reached. Any response, even an error, means that the server's certificate was trusted.
+    async fn sdk_unreachable(url: &str) -> bool {
+        let client = Client::new(Some(ClientSettings {
+            identity_url: url.to_string(),
+            api_url: url.to_string(),
+            user_agent: "bitwarden/sm-action".to_string(),
+            device_type: DeviceType::SDK,
+        }));
+        let login_request = AccessTokenLoginRequest {
+            access_token: format!("0.{}.secret:{}", Uuid::new_v4(), STANDARD.encode([7u8; 16])),
+            state_file: None,
+        };
+
+        match client.auth().login_access_token(&login_request).await {
+            Ok(_) => false,
+            Err(e) => matches!(
+                ActionError::from_login(&anyhow::anyhow!("{e}"), url),
+                ActionError::Unreachable { .. }
+            ),
+        }
+    }
+
+    const SUBPROCESS_VAR: &str = "SM_ACTION_TLS_TEST_SUBPROCESS";
+
+    // configure changes SSL_CERT_FILE for the whole process, and other tests read the environment
+    // from their own threads, so the test reruns itself in a process that runs nothing else
+    #[test]
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn test_ca_bundle_is_trusted_by_the_sdk_and_http_client() {
+        let name = "tls::tests::test_ca_bundle_is_trusted_by_the_sdk_and_http_client";
+        if std::env::var_os(SUBPROCESS_VAR).is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([name, "--exact", "--nocapture", "--test-threads=1"])
+                .env(SUBPROCESS_VAR, "1")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(
+                output.status.success() && stdout.contains("1 passed"),
+                "{stdout}{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return;
+        }
+
+        // a current-thread runtime starts no threads that could read the environment
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let server = runtime.block_on(tls_stub_server());
+        let config = Config {
+            ca_bundle: Some(testdata("ca.pem")),
+            ..Default::default()
+        };
+        let system = system_roots();
+
+        runtime.block_on(async {
+            assert!(sdk_unreachable(&server).await);
+            assert!(
+                http_client(&config)
+                    .unwrap()
+                    .get(&server)
+                    .send()
+                    .await
+                    .is_err()
+            );
+        });
+
+        configure(&config).unwrap();
+
+        runtime.block_on(async {
+            assert!(!sdk_unreachable(&server).await);
+            let response = http_client(&config)
+                .unwrap()
+                .get(&server)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.text().await.unwrap(), "ok");
+        });
+
+        // the system CA certificates are still trusted
+        let roots = std::fs::read_to_string(std::env::var("SSL_CERT_FILE").unwrap()).unwrap();
+        assert!(roots.starts_with(&system));
+        assert!(roots.ends_with(&std::fs::read_to_string(testdata("ca.pem")).unwrap()));
+    }
+
+    #[test]
+    fn test_pem() {
+        let der = CertificateDer::from_pem_slice(&read_testdata("ca.pem")).unwrap();
+        let pem = pem(der.as_ref());
+
+        assert!(pem.lines().all(|line| line.len() <= 64));
+        assert_eq!(CertificateDer::from_pem_slice(pem.as_bytes()).unwrap(), der);
+    }
+
+    #[test]
+    fn test_load_ca_bundle_invalid() {
+        assert!(load_ca_bundle(&testdata("missing.pem")).is_err());
+        // a key is not a certificate
+        assert!(load_ca_bundle(&testdata("server-key.pem")).is_err());
+    }
+}
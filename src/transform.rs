@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::config::get_env;
+use crate::output::write_atomic;
+
+/// A step applied to a secret value before it is set, written after the name:
+/// `UUID > KEYSTORE | base64decode | tofile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Base64Decode,
+    Base64Encode,
+    Trim,
+    /// Writes the value to a file and sets the file's path instead. Must come last.
+    ToFile,
+}
+
+impl FromStr for Transform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "base64decode" => Ok(Self::Base64Decode),
+            "base64encode" => Ok(Self::Base64Encode),
+            "trim" => Ok(Self::Trim),
+            "tofile" => Ok(Self::ToFile),
+            other => bail!(
+                "Unknown transform '{other}'. Expected one of 'base64decode', 'base64encode', 'trim' or 'tofile'"
+            ),
+        }
+    }
+}
+
+/// Parses a chain of transforms, like `| base64decode | tofile`. `tofile` may only come last,
+/// since every later transform would apply to the path.
+pub fn parse_chain(s: &str) -> Result<Vec<Transform>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let Some(chain) = s.strip_prefix('|') else {
+        bail!("Expected '| transform', found: {s}");
+    };
+    let transforms = chain
+        .split('|')
+        .map(Transform::from_str)
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(i) = transforms.iter().position(|t| *t == Transform::ToFile)
+        && i != transforms.len() - 1
+    {
+        bail!("'tofile' must be the last transform");
+    }
+
+    Ok(transforms)
+}
+
+/// Applies the transforms in order. Values are handled as bytes in between, so that binary
+/// values can be decoded and written to a file; the final value must be valid UTF-8.
+///
+/// `mask` is called with every form of the value that is text: the value itself, the result of
+/// each transform, and so the contents `tofile` writes, but not the path it sets.
+pub fn apply(
+    transforms: &[Transform],
+    value: String,
+    mut mask: impl FnMut(&str),
+) -> Result<String> {
+    mask(&value);
+    let mut value = value.into_bytes();
+
+    for transform in transforms {
+        value = match transform {
+            Transform::Base64Decode => {
+                // base64 is often stored wrapped over several lines
+                let encoded: Vec<u8> = value
+                    .into_iter()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect();
+                STANDARD
+                    .decode(encoded)
+                    .map_err(|e| anyhow::anyhow!("The value is not valid base64.\nError: {e}"))?
+            }
+            Transform::Base64Encode => STANDARD.encode(&value).into_bytes(),
+            Transform::Trim => value.trim_ascii().to_vec(),
+            Transform::ToFile => write_temp_file(&value)?
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("The temporary file path is not valid UTF-8"))?
+                .as_bytes()
+                .to_vec(),
+        };
+
+        if *transform != Transform::ToFile
+            && let Ok(text) = std::str::from_utf8(&value)
+        {
+            mask(text);
+        }
+    }
+
+    String::from_utf8(value).map_err(|_| {
+        anyhow::anyhow!("The value is not valid UTF-8 text. Add '| tofile' to write it to a file")
+    })
+}
+
+/// Writes the contents to a new file in the runner's temporary directory, which is emptied after
+/// each job.
+pub fn write_temp_file(contents: &[u8]) -> Result<PathBuf> {
+    let dir = get_env("RUNNER_TEMP")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!("sm-action-{}", uuid::Uuid::new_v4()));
+    write_atomic(&path, contents, 0o600)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chain() {
+        assert_eq!(parse_chain("").unwrap(), vec![]);
+        assert_eq!(
+            parse_chain("| trim | base64decode|tofile").unwrap(),
+            vec![Transform::Trim, Transform::Base64Decode, Transform::ToFile]
+        );
+        assert!(parse_chain("trim").is_err());
+        assert!(parse_chain("| gunzip").is_err());
+        assert!(parse_chain("| tofile | trim").is_err());
+    }
+
+    fn apply_unmasked(transforms: &[Transform], value: &str) -> Result<String> {
+        apply(transforms, value.to_string(), |_| {})
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            apply_unmasked(&[Transform::Trim], "  value\n").unwrap(),
+            "value"
+        );
+        assert_eq!(
+            apply_unmasked(&[Transform::Base64Decode], "aGVs\nbG8=\n").unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            apply_unmasked(&[Transform::Base64Encode], "hello").unwrap(),
+            "aGVsbG8="
+        );
+        assert!(apply_unmasked(&[Transform::Base64Decode], "not base64!").is_err());
+
+        // binary values can only be written to a file
+        assert!(apply_unmasked(&[Transform::Base64Decode], "/w==").is_err());
+        let path = apply_unmasked(&[Transform::Base64Decode, Transform::ToFile], "/w==").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0xff]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_apply_masks_every_form() {
+        let mut masked = Vec::new();
+        apply(&[Transform::Base64Encode], "hello".to_string(), |value| {
+            masked.push(value.to_string())
+        })
+        .unwrap();
+        assert_eq!(masked, vec!["hello", "aGVsbG8="]);
+
+        let mut masked = Vec::new();
+        let path = apply(
+            &[Transform::Base64Decode, Transform::ToFile],
+            "aGVsbG8=".to_string(),
+            |value| masked.push(value.to_string()),
+        )
+        .unwrap();
+        assert_eq!(masked, vec!["aGVsbG8=", "hello"]);
+        let _ = std::fs::remove_file(path);
+    }
+}